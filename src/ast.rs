@@ -1,29 +1,107 @@
-use crate::lexer::Pos;
+use std::{path::PathBuf, rc::Rc};
+
+use crate::lexer::Span;
 
 pub type Program = Vec<Stmt>;
 
 #[derive(Clone, Debug)]
 pub enum Stmt {
     Let(Binding),
+    Type(TypeDecl),
+    Import(Import),
     Expr(Expr),
 }
 
+/// e.g. `import "lib.qua" as lib;` or `import { map, fold } from "lib.qua";`
 #[derive(Clone, Debug)]
-pub struct Binding {
+pub struct Import {
+    pub module: Rc<Module>,
+    /// Matched against the record of the module's exports. Either a name to
+    /// bind the whole record to, or a record pattern of the names imported.
+    pub pattern: Pattern,
+    pub span: Span,
+}
+
+/// A `.qua` file. It is only parsed (and run) once, no matter how many times
+/// it is imported.
+#[derive(Debug)]
+pub struct Module {
+    /// Canonicalized, so that every import of the same file agrees.
+    pub path: PathBuf,
+    /// The path as written in the first import of the module, for messages.
+    pub name: String,
+    pub source: String,
+    pub program: Program,
+    /// A record of the module's top-level bindings, evaluated after the
+    /// program.
+    pub exports: Record,
+    /// The exported variants, so that they can be used in patterns once
+    /// imported.
+    pub variants: Vec<VariantInfo>,
+}
+
+/// e.g. `type Shape = Circle(r) | Rect(w, h) | Empty;`
+#[derive(Clone, Debug)]
+pub struct TypeDecl {
+    pub variants: Vec<VariantDecl>,
+}
+#[derive(Clone, Debug)]
+pub struct VariantDecl {
+    /// Where the variant's constructor (or value, if it has no fields) is
+    /// bound.
     pub ident: Identifier,
+    pub info: VariantInfo,
+}
+
+/// What is known about a variant wherever it is used.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariantInfo {
+    pub type_name: String,
+    pub name: String,
+    /// The number of fields. `None` for variants written without parentheses,
+    /// which are values instead of constructors.
+    pub arity: Option<usize>,
+    /// The name and arity of every variant of the type (including this one),
+    /// in order.
+    pub siblings: Vec<(String, Option<usize>)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Binding {
+    /// Always an identifier for functions.
+    pub pattern: Pattern,
     pub metadata: BindingMetadata,
-    pub value: Expr,
+    /// Shared, so that making a closure from a function doesn't copy its
+    /// body.
+    pub value: Rc<Expr>,
+    /// For a variable, the type of the value. For a function, the type it
+    /// returns.
+    pub annotation: Option<Annotation>,
+    pub span: Span,
+    /// The `///` comment just before the binding, if any.
+    pub doc: Option<String>,
 }
 
 #[derive(Clone, Debug)]
 pub enum BindingMetadata {
     Var,
     Func {
-        arguments: Vec<Identifier>,
+        arguments: Vec<Pattern>,
+        /// The annotation of each argument, if it has one.
+        annotations: Vec<Option<Annotation>>,
         upvalues: Vec<Upvalue>,
     },
 }
 
+/// A type written in the source, e.g. the `: Num` in `let x: Num = 1;`.
+#[derive(Clone, Copy, Debug)]
+pub enum Annotation {
+    Num,
+    Str,
+    Bool,
+    Nil,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Upvalue {
     pub target: IdentLocation,
@@ -35,16 +113,42 @@ pub enum Expr {
     Block(Block),
     Call(Call),
     If(Box<IfExpr>),
+    Match(Box<Match>),
     Binary(Box<BinaryExpr>),
     Unary(Box<UnaryExpr>),
     Literal(Literal),
+    Interpolated(Interpolated),
+    List(List),
+    Index(Box<Index>),
+    Record(Record),
+    Field(Box<FieldAccess>),
     Identifier(Identifier),
 }
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Block(block) => block.span,
+            Expr::Call(call) => call.span,
+            Expr::If(if_expr) => if_expr.span,
+            Expr::Match(match_expr) => match_expr.span,
+            Expr::Binary(binary_expr) => binary_expr.span,
+            Expr::Unary(unary_expr) => unary_expr.span,
+            Expr::Literal(literal) => literal.span,
+            Expr::Interpolated(interpolated) => interpolated.span,
+            Expr::List(list) => list.span,
+            Expr::Index(index) => index.span,
+            Expr::Record(record) => record.span,
+            Expr::Field(field_access) => field_access.span,
+            Expr::Identifier(identifier) => identifier.span,
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Block {
     pub stmts: Vec<Stmt>,
     pub return_expr: Option<Box<Expr>>,
+    pub span: Span,
 }
 
 #[derive(Clone, Debug)]
@@ -52,7 +156,7 @@ pub struct Call {
     pub target: Box<Expr>,
     pub arguments: Vec<Expr>,
     pub is_tail_call: bool,
-    pub pos: Pos,
+    pub span: Span,
 }
 
 #[derive(Clone, Debug)]
@@ -60,6 +164,7 @@ pub struct IfExpr {
     pub condition: Expr,
     pub then_block: Block,
     pub else_block: Option<ElseBlock>,
+    pub span: Span,
 }
 #[derive(Clone, Debug)]
 pub enum ElseBlock {
@@ -67,15 +172,28 @@ pub enum ElseBlock {
     Else(Block),
 }
 
+#[derive(Clone, Debug)]
+pub struct Match {
+    pub value: Expr,
+    pub arms: Vec<MatchArm>,
+    pub span: Span,
+}
+#[derive(Clone, Debug)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
 #[derive(Clone, Debug)]
 pub struct BinaryExpr {
     pub lhs: Expr,
     pub op: BinaryOp,
     pub rhs: Expr,
 
-    pub op_pos: Pos,
+    pub span: Span,
 }
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum BinaryOp {
     Or,
     And,
@@ -99,6 +217,7 @@ pub enum BinaryOp {
 pub struct UnaryExpr {
     pub op: UnaryOp,
     pub rhs: Expr,
+    pub span: Span,
 }
 #[derive(Clone, Debug)]
 pub enum UnaryOp {
@@ -107,23 +226,141 @@ pub enum UnaryOp {
 }
 
 #[derive(Clone, Debug)]
-pub enum Literal {
+pub struct Literal {
+    pub value: LiteralValue,
+    pub span: Span,
+}
+#[derive(Clone, Debug)]
+pub enum LiteralValue {
     Bool(bool),
     Number(f64),
     Str(String),
     Nil,
 }
 
+/// A string with expressions interpolated into it, e.g. `"({x}, {y})"`.
+///
+/// Each part is converted to a string and concatenated. The literal parts of
+/// the string are just `Str` literals.
+#[derive(Clone, Debug)]
+pub struct Interpolated {
+    pub parts: Vec<Expr>,
+    pub span: Span,
+}
+
+/// A list literal, e.g. `[1, 2, 3]`.
+#[derive(Clone, Debug)]
+pub struct List {
+    pub items: Vec<Expr>,
+    pub span: Span,
+}
+
+/// Indexing into a list, e.g. `xs[i]`.
+#[derive(Clone, Debug)]
+pub struct Index {
+    pub target: Expr,
+    pub index: Expr,
+    pub span: Span,
+}
+
+/// A record literal, e.g. `{ x: 1, y: 2 }`, or a functional update of an
+/// existing record, e.g. `{ p with x: 3 }`.
+///
+/// An update can only replace fields that `base` already has.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub base: Option<Box<Expr>>,
+    pub fields: Vec<RecordField>,
+    pub span: Span,
+}
+#[derive(Clone, Debug)]
+pub struct RecordField {
+    pub name: String,
+    pub value: Expr,
+    pub span: Span,
+}
+
+/// Getting a field of a record, e.g. `p.x`.
+#[derive(Clone, Debug)]
+pub struct FieldAccess {
+    pub target: Expr,
+    pub field: String,
+    pub span: Span,
+}
+
+/// What a value is matched against (and destructured by) in a `let` or a
+/// function argument.
+///
+/// Names are bound in the order they appear, left to right.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// Matches anything, and binds it to the name.
+    Identifier(Identifier),
+    /// `_`, which matches anything and binds nothing.
+    Wildcard(Span),
+    /// Matches values equal to the literal.
+    Literal(Literal),
+    List(ListPattern),
+    Record(RecordPattern),
+    Variant(Box<VariantPattern>),
+}
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Identifier(identifier) => identifier.span,
+            Pattern::Wildcard(span) => *span,
+            Pattern::Literal(literal) => literal.span,
+            Pattern::List(list) => list.span,
+            Pattern::Record(record) => record.span,
+            Pattern::Variant(variant) => variant.span,
+        }
+    }
+}
+
+/// e.g. `Rect(w, h)`, or `Empty` for a variant without fields.
+#[derive(Clone, Debug)]
+pub struct VariantPattern {
+    pub info: VariantInfo,
+    pub fields: Vec<Pattern>,
+    pub span: Span,
+}
+
+/// e.g. `[first, second, ..rest]`. Without a `..rest`, the list must have
+/// exactly as many items as the pattern.
+#[derive(Clone, Debug)]
+pub struct ListPattern {
+    pub items: Vec<Pattern>,
+    /// Matched against a list of the remaining items. Always an identifier
+    /// or a wildcard.
+    pub rest: Option<Box<Pattern>>,
+    pub span: Span,
+}
+
+/// e.g. `{ x, y: [a, b] }`. The record may have other fields too.
+#[derive(Clone, Debug)]
+pub struct RecordPattern {
+    pub fields: Vec<FieldPattern>,
+    pub span: Span,
+}
+#[derive(Clone, Debug)]
+pub struct FieldPattern {
+    pub name: String,
+    pub pattern: Pattern,
+    pub span: Span,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Identifier {
     pub name: String,
     pub location: Option<IdentLocation>,
+    pub span: Span,
 }
 impl Identifier {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, span: Span) -> Self {
         Self {
             name,
             location: None,
+            span,
         }
     }
 
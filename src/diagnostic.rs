@@ -0,0 +1,667 @@
+use std::fmt::Display;
+
+use crate::{
+    interperter::{self, Arity},
+    lexer,
+    lexer::Span,
+    parser, type_checker,
+};
+
+/// Where a `nil` can come from without being written.
+const IMPLICIT_NIL_HINT: &str =
+    "an `if` without an `else`, or a block without a final expression, evaluates to `nil`";
+
+/// A human-readable error, ready to be rendered against the source it came
+/// from.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub hints: Vec<String>,
+    /// The file the span is in, if it isn't the main one.
+    pub file: Option<String>,
+    /// Other diagnostics (already rendered) that explain this one, like the
+    /// errors inside an imported module.
+    pub notes: Vec<String>,
+    /// The calls that led to the error, innermost first, with where each
+    /// was called from.
+    pub trace: Vec<(String, Span)>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+            hints: Vec::new(),
+            file: None,
+            notes: Vec::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::new(message)
+        }
+    }
+
+    pub fn span(mut self, span: Option<Span>) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hints.push(hint.into());
+        self
+    }
+
+    pub fn file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    pub fn note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn trace(mut self, trace: Vec<(String, Span)>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Render the diagnostic with the offending line of `source` and the span
+    /// underlined.
+    ///
+    /// e.g.
+    /// ```text
+    /// error: type error: expected Num, found Str
+    ///  --> 2:11
+    ///   |
+    /// 2 | print(1 + -"s");
+    ///   |            ^^^
+    /// ```
+    ///
+    /// Spans covering multiple lines are only underlined to the end of their
+    /// first line. Spans that aren't in `source` (like from a different REPL
+    /// line) aren't shown.
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+
+        let Some(span) = self.span.filter(|span| span.is_in(source)) else {
+            for hint in &self.hints {
+                out += &format!("  = hint: {hint}\n");
+            }
+            return out + &self.render_trace(source) + &self.render_notes();
+        };
+
+        let (line, col) = span.calculate_line_col(source);
+        let line_start = source[..span.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_text = source[line_start..].lines().next().unwrap_or("");
+        // Byte offsets into `line_text`
+        let (start, end) = (span.start - line_start, span.end - line_start);
+        let gutter = " ".repeat(line.to_string().len());
+
+        let file = self
+            .file
+            .as_ref()
+            .map(|f| format!("{f}:"))
+            .unwrap_or_default();
+        out += &format!("{gutter}--> {file}{line}:{col}\n");
+        out += &format!("{gutter} |\n");
+        out += &format!("{line} | {line_text}\n");
+
+        let before = &line_text[..start];
+        let padding: String = before
+            .chars()
+            // Keep tabs so that the underline lines up
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underlined = &line_text[start..end.min(line_text.len())];
+        // Always underline at least one char, even for empty spans
+        let underline = "^".repeat(underlined.chars().count().max(1));
+        out += &format!("{gutter} | {padding}{underline}\n");
+
+        for hint in &self.hints {
+            out += &format!("{gutter} = hint: {hint}\n");
+        }
+
+        out + &self.render_trace(source) + &self.render_notes()
+    }
+
+    /// e.g.
+    /// ```text
+    /// stack trace (innermost call first):
+    ///   in `inner`, called at 3:5
+    ///   in `outer`, called at 7:1 (2 times)
+    /// ```
+    ///
+    /// Runs of the same call (like from recursion) are shown once, and only
+    /// the innermost and outermost calls of a long trace are shown.
+    fn render_trace(&self, source: &str) -> String {
+        const SHOWN: usize = 10;
+
+        if self.trace.is_empty() {
+            return String::new();
+        }
+
+        let mut calls: Vec<(&str, Span, usize)> = Vec::new();
+        for (name, span) in &self.trace {
+            match calls.last_mut() {
+                Some((last_name, last_span, count)) if last_name == name && last_span == span => {
+                    *count += 1;
+                }
+                _ => calls.push((name, *span, 1)),
+            }
+        }
+
+        let file = self
+            .file
+            .as_ref()
+            .map(|f| format!("{f}:"))
+            .unwrap_or_default();
+        let render_call = |(name, span, count): &(&str, Span, usize)| {
+            let location = if span.is_in(source) {
+                let (line, col) = span.calculate_line_col(source);
+                format!(", called at {file}{line}:{col}")
+            } else {
+                String::new()
+            };
+            let times = if *count > 1 {
+                format!(" ({count} times)")
+            } else {
+                String::new()
+            };
+            format!("  in {name}{location}{times}\n")
+        };
+
+        let mut out = "stack trace (innermost call first):\n".to_string();
+        if calls.len() > SHOWN * 2 {
+            calls[..SHOWN].iter().for_each(|c| out += &render_call(c));
+            out += &format!("  ... {} more calls ...\n", calls.len() - SHOWN * 2);
+            calls[calls.len() - SHOWN..]
+                .iter()
+                .for_each(|c| out += &render_call(c));
+        } else {
+            calls.iter().for_each(|c| out += &render_call(c));
+        }
+        out
+    }
+
+    fn render_notes(&self) -> String {
+        self.notes.concat()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl From<&parser::Warning> for Diagnostic {
+    fn from(warning: &parser::Warning) -> Self {
+        use parser::WarningKind;
+
+        let diagnostic = match &warning.kind {
+            WarningKind::NonExhaustiveMatch { missing } => match missing {
+                Some(missing) => Diagnostic::warning(format!("`match` doesn't handle `{missing}`"))
+                    .hint(format!("add a `{missing} => ...` arm")),
+                None => Diagnostic::warning("`match` has no catch-all arm")
+                    .hint("add a `_ => ...` arm to handle every other value"),
+            },
+            WarningKind::DanglingDocComment => {
+                Diagnostic::warning("doc comment isn't followed by a `let` binding")
+                    .hint("use `//` for a regular comment")
+            }
+            WarningKind::InModule {
+                path,
+                source,
+                warnings,
+            } => warnings.iter().fold(
+                Diagnostic::warning(format!(
+                    "module `{path}` has {} {}",
+                    warnings.len(),
+                    plural(warnings.len(), "warning", "warnings"),
+                )),
+                |diagnostic, warning| {
+                    diagnostic.note(Diagnostic::from(warning).file(path).render(source))
+                },
+            ),
+        };
+        diagnostic.span(Some(warning.span))
+    }
+}
+
+impl From<&parser::Error> for Diagnostic {
+    fn from(err: &parser::Error) -> Self {
+        use lexer::TokenData;
+        use parser::ErrorKind;
+
+        let diagnostic = match &err.kind {
+            ErrorKind::ExpectedToken(expected) => {
+                let diagnostic = Diagnostic::new(format!("expected {expected}"));
+                match expected {
+                    TokenData::Semicolon => diagnostic
+                        .hint("`let` bindings and expression statements must end with `;`"),
+                    TokenData::Equals => diagnostic.hint(
+                        "bindings are written `let name = value;` or `let name(args) = body;`",
+                    ),
+                    TokenData::OpenBrace => {
+                        diagnostic.hint("the body of an `if` or `else` must be a `{ ... }` block")
+                    }
+                    TokenData::CloseBrace => {
+                        diagnostic.hint("only the last expression in a block may omit its `;`")
+                    }
+                    TokenData::Comma | TokenData::CloseParen => {
+                        diagnostic.hint("arguments are separated by `,` and closed with `)`")
+                    }
+                    TokenData::CloseBracket => {
+                        diagnostic.hint("list items are separated by `,` and closed with `]`")
+                    }
+                    _ => diagnostic,
+                }
+            }
+            ErrorKind::ExpectedIdentifier => Diagnostic::new("expected a name")
+                .hint("names start with a letter or `_`, and may contain digits"),
+            ErrorKind::ExpectedPrimary => Diagnostic::new("expected an expression").hint(
+                "try a number, string, name, `true`, `false`, `nil`, `if`, `match`, a `[...]` list, or a `{ ... }` block",
+            ),
+            ErrorKind::ExpectedStmt => Diagnostic::new("expected a statement")
+                .hint("add a `;` after the expression to make it a statement"),
+            ErrorKind::VarNotInScope { identifier } => {
+                Diagnostic::new(format!("`{}` is not in scope", identifier.name))
+                    .hint("names must be bound with `let` before they are used")
+            }
+            ErrorKind::InvalidToken(token_error) => Diagnostic::from(token_error),
+            ErrorKind::ExpectedPattern => Diagnostic::new("expected a pattern").hint(
+                "patterns are names, `_`, literals, lists like `[a, b, ..rest]`, or records like `{ x, y: pattern }`",
+            ),
+            ErrorKind::DuplicateField { name } => {
+                Diagnostic::new(format!("field `{name}` is given more than once"))
+            }
+            ErrorKind::NotAVariant { name } => {
+                Diagnostic::new(format!("`{name}` is not a variant"))
+                    .hint("variants are declared with `type Name = Variant(fields) | ...;`")
+            }
+            ErrorKind::VariantArity {
+                name,
+                expected,
+                given,
+            } => Diagnostic::new(format!(
+                "`{name}` has {expected} {}, but the pattern has {given}",
+                plural(*expected, "field", "fields"),
+            )),
+            ErrorKind::IncorrectArity {
+                name,
+                expected,
+                given,
+            } => Diagnostic::new(format!(
+                "`{name}` takes {}, but {given} {} given",
+                describe_arity(*expected),
+                plural(*given, "was", "were"),
+            )),
+            ErrorKind::ExpectedType => Diagnostic::new("expected a type")
+                .hint("the types that can be written are `Num`, `Str`, `Bool` and `Nil`"),
+            ErrorKind::UnknownType { name } => Diagnostic::new(format!("unknown type `{name}`"))
+                .hint("the types that can be written are `Num`, `Str`, `Bool` and `Nil`"),
+            ErrorKind::ExpectedModulePath => Diagnostic::new("expected the path of a module")
+                .hint("imports look like `import \"lib.qua\" as lib;` or `import { name } from \"lib.qua\";`"),
+            ErrorKind::ModuleNotFound { path, reason } => {
+                Diagnostic::new(format!("can't open module `{path}`: {reason}"))
+                    .hint("module paths are relative to the file importing them")
+            }
+            ErrorKind::ImportCycle { cycle } => {
+                let cycle: Vec<_> = cycle.iter().map(|path| format!("`{path}`")).collect();
+                Diagnostic::new(format!("import cycle: {}", cycle.join(" -> ")))
+                    .hint("move the bindings both modules need into a module of their own")
+            }
+            ErrorKind::InvalidModule {
+                path,
+                source,
+                errors,
+            } => errors.iter().fold(
+                Diagnostic::new(format!(
+                    "module `{path}` has {} {}",
+                    errors.len(),
+                    plural(errors.len(), "error", "errors"),
+                )),
+                |diagnostic, err| diagnostic.note(Diagnostic::from(err).file(path).render(source)),
+            ),
+            ErrorKind::NotExported { name, path } => {
+                Diagnostic::new(format!("module `{path}` doesn't export `{name}`"))
+                    .hint("a module exports its top-level `let` bindings and variants")
+            }
+        };
+        match err.span {
+            Some(span) => diagnostic.span(Some(span)),
+            // The parser only loses track of the position when it runs out of
+            // tokens.
+            None => Diagnostic {
+                message: format!("{}, found end of input", diagnostic.message),
+                ..diagnostic
+            },
+        }
+    }
+}
+
+impl From<&type_checker::Error> for Diagnostic {
+    fn from(err: &type_checker::Error) -> Self {
+        use type_checker::{ErrorKind, Type};
+
+        let diagnostic = match &err.kind {
+            ErrorKind::Mismatch { expected, found } => {
+                let diagnostic =
+                    Diagnostic::new(format!("type error: expected {expected}, found {found}"));
+                match (expected, found) {
+                    (Type::Func(..), Type::Func(..)) => diagnostic
+                        .hint("functions must be called with as many arguments as they take"),
+                    _ => diagnostic,
+                }
+            }
+            ErrorKind::ImplicitNil { expected } => {
+                Diagnostic::new(format!("type error: expected {expected}, found Nil"))
+                    .hint(IMPLICIT_NIL_HINT)
+            }
+            ErrorKind::AnnotationMismatch { expected, found } => {
+                Diagnostic::new(format!("type error: expected {expected}, found {found}"))
+                    .hint(format!("the value is annotated as `{expected}`"))
+            }
+            ErrorKind::NotAFunction { found } => {
+                Diagnostic::new(format!("type error: expected a function, found {found}"))
+                    .hint("only functions can be called")
+            }
+            ErrorKind::NotARecord { found } => {
+                Diagnostic::new(format!("type error: expected a record, found {found}"))
+            }
+            ErrorKind::NotAddable { found } => {
+                Diagnostic::new(format!("type error: expected Num or Str, found {found}"))
+                    .hint("`+` adds numbers, or joins strings")
+            }
+            ErrorKind::NoSuchField { field, record } => {
+                Diagnostic::new(format!("record {record} has no field `{field}`"))
+            }
+            ErrorKind::WrongNumberOfArguments { expected, given } => Diagnostic::new(format!(
+                "expected {expected} {}, but {given} {} given",
+                plural(*expected, "argument", "arguments"),
+                plural(*given, "was", "were"),
+            )),
+            ErrorKind::InModule {
+                name,
+                source,
+                errors,
+            } => errors.iter().fold(
+                Diagnostic::new(format!(
+                    "module `{name}` has {} type {}",
+                    errors.len(),
+                    plural(errors.len(), "error", "errors"),
+                )),
+                |diagnostic, err| diagnostic.note(Diagnostic::from(err).file(name).render(source)),
+            ),
+        };
+        diagnostic.span(Some(err.span))
+    }
+}
+
+impl From<&interperter::Error> for Diagnostic {
+    fn from(err: &interperter::Error) -> Self {
+        use interperter::{DiagnosticType, ErrorKind};
+
+        let diagnostic = match &err.kind {
+            ErrorKind::TypeError { expected, actual } => {
+                let diagnostic =
+                    Diagnostic::new(format!("type error: expected {expected}, found {actual}"));
+                match (expected, actual) {
+                    (DiagnosticType::Func, _) => {
+                        diagnostic.hint("only functions can be called")
+                    }
+                    (DiagnosticType::Num, DiagnosticType::Nil) => diagnostic.hint(IMPLICIT_NIL_HINT),
+                    _ => diagnostic,
+                }
+            }
+            ErrorKind::IOError(err) => Diagnostic::new(format!("I/O error: {err}")),
+            ErrorKind::IncorrectArity { given, correct } => Diagnostic::new(format!(
+                "expected {}, but {given} {} given",
+                describe_arity(*correct),
+                plural(*given, "was", "were"),
+            )),
+            ErrorKind::NoSuchField { field } => {
+                Diagnostic::new(format!("record has no field `{field}`"))
+            }
+            ErrorKind::PatternMismatch { value } => {
+                Diagnostic::new(format!("pattern doesn't match the value `{value}`"))
+            }
+            ErrorKind::NoMatchingArm { value } => {
+                Diagnostic::new(format!("no `match` arm matches the value `{value}`"))
+                    .hint("add a `_ => ...` arm to handle every other value")
+            }
+            ErrorKind::IndexOutOfBounds { index, len } => Diagnostic::new(format!(
+                "index {index} is out of bounds for a list of length {len}"
+            ))
+            .hint("indices are whole numbers, starting at 0"),
+            ErrorKind::MissingArgument { index } => {
+                Diagnostic::new(format!("missing argument {}", index + 1))
+            }
+            ErrorKind::Host(message) => Diagnostic::new(message),
+            ErrorKind::ForeignFunc { func } => {
+                Diagnostic::new(format!("`{func}` was made by an engine with a different runtime"))
+                    .hint("a function can only be called by the kind of engine that made it")
+            }
+            ErrorKind::InModule {
+                name,
+                source,
+                error,
+            } => Diagnostic::new(format!("error while running module `{name}`"))
+                .note(Diagnostic::from(error.as_ref()).file(name).render(source)),
+        };
+        let trace = err
+            .trace
+            .iter()
+            .map(|frame| {
+                let name = match frame.tail_calls {
+                    0 => format!("`{}`", frame.name),
+                    n => format!(
+                        "`{}` (after {n} tail {})",
+                        frame.name,
+                        plural(n, "call", "calls")
+                    ),
+                };
+                (name, frame.call_site)
+            })
+            .collect();
+        diagnostic.span(err.span).trace(trace)
+    }
+}
+
+impl From<&lexer::TokenError> for Diagnostic {
+    fn from(err: &lexer::TokenError) -> Self {
+        use lexer::TokenError;
+
+        match err {
+            TokenError::UnexpectedChar(c) => {
+                Diagnostic::new(format!("unexpected character `{}`", c.escape_debug()))
+            }
+            TokenError::UnterminatedStringLiteral => Diagnostic::new("unterminated string literal")
+                .hint("add a closing `\"` to end the string"),
+            TokenError::InvalidEscape(c) => Diagnostic::new(format!(
+                "invalid escape sequence `\\{}`",
+                c.escape_debug()
+            ))
+            .hint("valid escapes are `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\\"`, `\\{`, `\\}` and `\\u{...}`")
+            .hint("use a raw string, like `r\"C:\\path\"`, to avoid escapes entirely"),
+            TokenError::InvalidUnicodeEscape => Diagnostic::new("invalid unicode escape")
+                .hint("unicode escapes are 1-6 hex digits in braces, like `\\u{1F600}`"),
+            TokenError::MalformedRawString => Diagnostic::new("malformed raw string literal")
+                .hint("raw strings look like `r\"...\"`, or `r#\"...\"#` to allow `\"` inside"),
+            TokenError::UnterminatedBlockComment => {
+                Diagnostic::new("unterminated block comment")
+                    .hint("add a closing `*/`; block comments nest, so every `/*` needs one")
+            }
+            TokenError::MalformedNumber(text) => {
+                Diagnostic::new(format!("malformed number literal `{text}`")).hint(
+                    "numbers look like `42`, `1_000`, `3.14`, `1.5e-3`, `0xFF`, `0o17` or `0b1010`",
+                )
+            }
+        }
+    }
+}
+
+fn describe_arity(arity: Arity) -> String {
+    match arity.max {
+        Some(max) if max == arity.min => {
+            format!("{max} {}", plural(max, "argument", "arguments"))
+        }
+        Some(max) => format!("{} to {max} arguments", arity.min),
+        None => format!(
+            "at least {} {}",
+            arity.min,
+            plural(arity.min, "argument", "arguments")
+        ),
+    }
+}
+
+fn plural<'a>(n: usize, singular: &'a str, plural: &'a str) -> &'a str {
+    if n == 1 {
+        singular
+    } else {
+        plural
+    }
+}
+
+impl Display for lexer::TokenData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use lexer::TokenData::*;
+        let symbol = match self {
+            Let => "let",
+            Semicolon => ";",
+            Equals => "=",
+            OpenBrace => "{",
+            CloseBrace => "}",
+            If => "if",
+            Else => "else",
+            Or => "or",
+            And => "and",
+            Bang => "!",
+            BangEquals => "!=",
+            EqualsEquals => "==",
+            Less => "<",
+            LessEquals => "<=",
+            Greater => ">",
+            GreaterEquals => ">=",
+            Minus => "-",
+            Plus => "+",
+            Slash => "/",
+            Star => "*",
+            True => "true",
+            False => "false",
+            Nil => "nil",
+            OpenParen => "(",
+            CloseParen => ")",
+            OpenBracket => "[",
+            CloseBracket => "]",
+            Comma => ",",
+            Colon => ":",
+            Dot => ".",
+            DotDot => "..",
+            With => "with",
+            Match => "match",
+            FatArrow => "=>",
+            Type => "type",
+            Pipe => "|",
+            Import => "import",
+            Number(n) => return write!(f, "number `{n}`"),
+            Str(s) => return write!(f, "string {s:?}"),
+            StrHead(_) | StrMid(_) | StrTail(_) => return write!(f, "interpolated string"),
+            Identifier(name) => return write!(f, "`{name}`"),
+            DocComment(_) => return write!(f, "doc comment"),
+            Error(_) => return write!(f, "invalid token"),
+        };
+        write!(f, "`{symbol}`")
+    }
+}
+
+impl Display for interperter::DiagnosticType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use interperter::DiagnosticType::*;
+        let name = match self {
+            Bool => "Bool",
+            Num => "Num",
+            Str => "Str",
+            Func => "Func",
+            List => "List",
+            Record => "Record",
+            Variant => "Variant",
+            Nil => "Nil",
+            TailCall => "<tail call>",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Type variables are written `a`, `b`, ..., `z`, `a1`, ...
+impl Display for type_checker::Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use type_checker::Type::*;
+        match self {
+            Var(var) => {
+                let i = var.index();
+                let letter = (b'a' + (i % 26) as u8) as char;
+                match i / 26 {
+                    0 => write!(f, "{letter}"),
+                    n => write!(f, "{letter}{n}"),
+                }
+            }
+            Any => write!(f, "Any"),
+            Num => write!(f, "Num"),
+            Str => write!(f, "Str"),
+            Bool => write!(f, "Bool"),
+            Nil => write!(f, "Nil"),
+            List(item) => write!(f, "[{item}]"),
+            Record(fields) => {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|(name, ty)| format!("{name}: {ty}"))
+                    .collect();
+                write!(f, "{{ {} }}", fields.join(", "))
+            }
+            Func(args, ret) => {
+                let args: Vec<_> = args.iter().map(ToString::to_string).collect();
+                write!(f, "({}) -> {ret}", args.join(", "))
+            }
+            Variant(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl Span {
+    fn is_in(&self, source: &str) -> bool {
+        source.is_char_boundary(self.start) && source.is_char_boundary(self.end)
+    }
+
+    /// (line, col) of the start of the span, both starting at 1. `col`
+    /// counts chars, not bytes.
+    ///
+    /// e.g.
+    /// abc
+    /// def
+    /// f -> (2, 3)
+    fn calculate_line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        (line, before[line_start..].chars().count() + 1)
+    }
+}
@@ -0,0 +1,388 @@
+mod convert;
+
+use std::{fmt::Display, path::Path, rc::Rc};
+
+pub use convert::{FromValue, IntoValue};
+
+use crate::{
+    ast::{IdentLocation, Program},
+    diagnostic::Diagnostic,
+    interperter::{self, DiagnosticType, Func, NativeFunc, Value},
+    lexer, parser, type_checker, vm,
+};
+
+/// Runs qua code. Everything defined stays defined for the next run, like in
+/// a REPL.
+///
+/// ```
+/// use qua_lang::{Engine, IntoValue};
+///
+/// let mut engine = Engine::new();
+/// engine.eval("let add(a, b) = a + b;").unwrap();
+/// let sum: f64 = engine
+///     .call("add", vec![1.0.into_value(), 2.0.into_value()])
+///     .unwrap();
+/// assert_eq!(sum, 3.0);
+/// ```
+pub struct Engine {
+    parser_env: parser::Env,
+    type_env: type_checker::Env,
+    runtime: Runtime,
+    /// The functions given to `register`, so they survive a `reset`.
+    natives: Vec<NativeFunc>,
+    /// Given each warning, rendered, as soon as it is found.
+    on_warning: Box<dyn FnMut(&str)>,
+}
+
+/// What runs a program once it has been checked.
+#[derive(Clone)]
+enum Runtime {
+    Interpreter(interperter::Env),
+    /// Compiles to bytecode first.
+    Vm(vm::Env),
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::with_runtime(Runtime::Interpreter(interperter::Env::new()))
+    }
+
+    /// An engine that runs code on the bytecode VM.
+    pub fn with_vm() -> Self {
+        Self::with_runtime(Runtime::Vm(vm::Env::new()))
+    }
+
+    fn with_runtime(runtime: Runtime) -> Self {
+        Engine {
+            parser_env: parser::Env::new(),
+            type_env: type_checker::Env::new(),
+            runtime,
+            natives: Vec::new(),
+            on_warning: Box::new(|_| {}),
+        }
+    }
+
+    /// Forgets everything defined by code that has been run, keeping the
+    /// registered functions and the warning handler.
+    pub fn reset(&mut self) {
+        self.parser_env = parser::Env::new();
+        self.type_env = type_checker::Env::new();
+        self.runtime = match self.runtime {
+            Runtime::Interpreter(_) => Runtime::Interpreter(interperter::Env::new()),
+            Runtime::Vm(_) => Runtime::Vm(vm::Env::new()),
+        };
+        for native in std::mem::take(&mut self.natives) {
+            self.register(native);
+        }
+    }
+
+    /// Makes a Rust function callable from qua, like the stdlib functions.
+    /// It can capture whatever it needs, like a database handle.
+    ///
+    /// ```
+    /// use std::{cell::Cell, rc::Rc};
+    ///
+    /// use qua_lang::{Arity, Engine, NativeFunc, Value};
+    ///
+    /// let count = Rc::new(Cell::new(0.0));
+    /// let counter = count.clone();
+    /// let mut engine = Engine::new();
+    /// engine.register(NativeFunc::new("count", Arity::exact(0), move |_, _| {
+    ///     counter.set(counter.get() + 1.0);
+    ///     Ok(Value::Num(counter.get()))
+    /// }));
+    /// engine.eval("count(); count();").unwrap();
+    /// assert_eq!(count.get(), 2.0);
+    /// ```
+    ///
+    /// Imported modules can call it too:
+    ///
+    /// ```
+    /// # use std::{fs, path::Path};
+    /// # use qua_lang::{Arity, Engine, NativeFunc, Value};
+    /// # let dir = std::env::temp_dir().join("qua_register_doctest");
+    /// # fs::create_dir_all(&dir).unwrap();
+    /// fs::write(dir.join("twice.qua"), "let twice(x) = double(double(x));").unwrap();
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register(NativeFunc::new("double", Arity::exact(1), |_, args| {
+    ///     match args[0] {
+    ///         Value::Num(n) => Ok(Value::Num(n * 2.0)),
+    ///         _ => Ok(Value::Nil),
+    ///     }
+    /// }));
+    /// let main = dir.join("main.qua");
+    /// let four = engine.eval_file(&main, r#"import "twice.qua" as t; t.twice(1);"#);
+    /// assert!(matches!(four, Ok(Value::Num(4.0))));
+    /// ```
+    pub fn register(&mut self, native: NativeFunc) {
+        self.natives.push(native.clone());
+        self.parser_env
+            .declare_host_func(native.name.clone(), native.arity);
+        self.type_env
+            .declare_host(native.name.clone(), native.ty.clone());
+        let value = Value::Func(Func::Native(Rc::new(native)));
+        match &mut self.runtime {
+            Runtime::Interpreter(env) => env.define_host(value),
+            Runtime::Vm(env) => env.define_host(value),
+        }
+    }
+
+    /// Sets the file being run, which imports are relative to, for every
+    /// run after this.
+    pub fn set_path(&mut self, path: &Path) {
+        self.parser_env.set_path(path);
+    }
+
+    /// Runs the source of the file at `path`, like `eval`. Imports are
+    /// relative to it for this run only.
+    pub fn eval_file(&mut self, path: &Path, source: &str) -> Result<Value> {
+        let previous = self.parser_env.set_path(path);
+        let result = self.eval(source);
+        self.parser_env.restore_path(previous);
+        result
+    }
+
+    /// Sets what to do with warnings, which are ignored by default. Each is
+    /// given rendered, before anything is run.
+    pub fn on_warning(&mut self, on_warning: impl FnMut(&str) + 'static) {
+        self.on_warning = Box::new(on_warning);
+    }
+
+    /// Parses the source without declaring anything it defines, so it can
+    /// be looked at without changing what later code sees.
+    pub fn parse(&self, source: &str) -> Result<Program> {
+        let tokens = lexer::lex(source.to_string());
+        let (ast, errors, _) = parser::parse(tokens, &mut self.parser_env.clone());
+        if !errors.is_empty() {
+            return Err(Error::Parse {
+                errors,
+                source: source.to_string(),
+            });
+        }
+        Ok(ast)
+    }
+
+    /// Parses and type checks the source, without running it. Nothing it
+    /// defines is kept, since it was never run.
+    ///
+    /// ```
+    /// use qua_lang::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.check("let a = 1;").unwrap();
+    /// assert!(engine.eval("a;").is_err());
+    /// engine.eval("let b = 2; b;").unwrap();
+    /// ```
+    pub fn check(&mut self, source: &str) -> Result<Program> {
+        let snapshot = self.snapshot();
+        let result = self.check_unguarded(source);
+        self.restore(snapshot);
+        result
+    }
+
+    fn check_unguarded(&mut self, source: &str) -> Result<Program> {
+        let tokens = lexer::lex(source.to_string());
+        let (ast, errors, warnings) = parser::parse(tokens, &mut self.parser_env);
+
+        for warning in &warnings {
+            (self.on_warning)(&Diagnostic::from(warning).render(source));
+        }
+        if !errors.is_empty() {
+            return Err(Error::Parse {
+                errors,
+                source: source.to_string(),
+            });
+        }
+
+        let errors = type_checker::check(&ast, &mut self.type_env);
+        if !errors.is_empty() {
+            return Err(Error::Type {
+                errors,
+                source: source.to_string(),
+            });
+        }
+        Ok(ast)
+    }
+
+    /// Runs the source. Returns the value of the last statement if it is an
+    /// expression, and `nil` otherwise. If there are any errors, nothing it
+    /// defines is kept, even what was defined before the error happened.
+    pub fn eval(&mut self, source: &str) -> Result<Value> {
+        let snapshot = self.snapshot();
+        self.eval_unguarded(source)
+            .inspect_err(|_| self.restore(snapshot))
+    }
+
+    fn eval_unguarded(&mut self, source: &str) -> Result<Value> {
+        let ast = self.check_unguarded(source)?;
+        let result = match &mut self.runtime {
+            Runtime::Interpreter(env) => interperter::interpert(ast, env),
+            Runtime::Vm(env) => vm::run(&ast, env),
+        };
+        result.map_err(|error| Error::Runtime {
+            error: Box::new(error),
+            source: Some(source.to_string()),
+        })
+    }
+
+    /// Everything defined at the top level (not including the stdlib or
+    /// registered functions), in the order it was defined.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        self.parser_env
+            .globals()
+            .into_iter()
+            .map(|(name, index)| {
+                let value = match &self.runtime {
+                    Runtime::Interpreter(env) => env.get(IdentLocation::Stack(index)),
+                    Runtime::Vm(env) => env.get(index),
+                };
+                (name, value.clone())
+            })
+            .filter(|(name, value)| {
+                !matches!(value, Value::Func(Func::Native(native)) if &native.name == name)
+            })
+            .collect()
+    }
+
+    /// Calls the function bound to `name` at the top level.
+    ///
+    /// Functions can be passed between engines, but only called by one with
+    /// the same runtime as the engine that made them:
+    ///
+    /// ```
+    /// use qua_lang::{Engine, Value};
+    ///
+    /// let mut vm = Engine::with_vm();
+    /// let double: Value = vm.eval("let double(x) = x * 2; double;").unwrap();
+    ///
+    /// let mut interpreter = Engine::new();
+    /// interpreter.eval("let apply(f) = f(1);").unwrap();
+    /// let result = interpreter.call::<Value>("apply", vec![double]);
+    /// assert!(result.is_err());
+    /// ```
+    pub fn call<R: FromValue>(&mut self, name: &str, arguments: Vec<Value>) -> Result<R> {
+        let Some(IdentLocation::Stack(index)) = self.parser_env.resolve(name) else {
+            return Err(Error::Undefined {
+                name: name.to_string(),
+            });
+        };
+        let func = match &self.runtime {
+            Runtime::Interpreter(env) => env.get(IdentLocation::Stack(index)),
+            Runtime::Vm(env) => env.get(index),
+        };
+        let Value::Func(func) = func.clone() else {
+            return Err(Error::Conversion {
+                expected: DiagnosticType::Func,
+                actual: func.into(),
+            });
+        };
+
+        let snapshot = self.snapshot();
+        let result = match &mut self.runtime {
+            Runtime::Interpreter(env) => interperter::call(func, arguments, env),
+            Runtime::Vm(env) => vm::call(func, arguments, env),
+        };
+        if result.is_err() {
+            self.restore(snapshot);
+        }
+        let value = result.map_err(|error| Error::Runtime {
+            error: Box::new(error),
+            source: None,
+        })?;
+        R::from_value(value)
+    }
+}
+
+/// Everything code can change, so that it can be undone if the code has an
+/// error. The parser, type checker, and runtime each keep track of where
+/// things are defined, so they have to be rolled back together.
+struct Snapshot {
+    parser_env: parser::Env,
+    type_env: type_checker::Env,
+    runtime: Runtime,
+}
+
+impl Engine {
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            parser_env: self.parser_env.clone(),
+            type_env: self.type_env.clone(),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.parser_env = snapshot.parser_env;
+        self.type_env = snapshot.type_env;
+        self.runtime = snapshot.runtime;
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Parse {
+        errors: Vec<parser::Error>,
+        source: String,
+    },
+    Type {
+        errors: Vec<type_checker::Error>,
+        source: String,
+    },
+    Runtime {
+        error: Box<interperter::Error>,
+        /// `None` if the error came from `Engine::call`, in which case it
+        /// isn't shown where the error happened.
+        source: Option<String>,
+    },
+    /// There is nothing with that name to `Engine::call`.
+    Undefined { name: String },
+    /// A value is a different type than it was converted to.
+    Conversion {
+        expected: DiagnosticType,
+        actual: DiagnosticType,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn aborting<E>(errors: &[E], source: &str) -> String
+        where
+            for<'e> Diagnostic: From<&'e E>,
+        {
+            let rendered: String = errors
+                .iter()
+                .map(|err| Diagnostic::from(err).render(source))
+                .collect();
+            format!(
+                "{rendered}aborting due to {} error{}",
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" }
+            )
+        }
+
+        match self {
+            Error::Parse { errors, source } => write!(f, "{}", aborting(errors, source)),
+            Error::Type { errors, source } => write!(f, "{}", aborting(errors, source)),
+            Error::Runtime { error, source } => {
+                let rendered =
+                    Diagnostic::from(error.as_ref()).render(source.as_deref().unwrap_or(""));
+                write!(f, "{}", rendered.trim_end())
+            }
+            Error::Undefined { name } => write!(f, "error: `{name}` is not defined"),
+            Error::Conversion { expected, actual } => {
+                write!(f, "error: expected {expected}, found {actual}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,151 @@
+use std::rc::Rc;
+
+use super::{Error, Result};
+use crate::{
+    interperter::{DiagnosticType, Value},
+    util::persistent_vec::PVec,
+};
+
+/// Converts a Rust value into a qua value, like for the arguments of
+/// `Engine::call`.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// Converts a qua value into a Rust value, like for the result of
+/// `Engine::call`.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self>;
+}
+
+fn mismatch<T>(expected: DiagnosticType, actual: &Value) -> Result<T> {
+    Err(Error::Conversion {
+        expected,
+        actual: actual.into(),
+    })
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Nil => Ok(()),
+            value => mismatch(DiagnosticType::Nil, &value),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Bool(bool) => Ok(bool),
+            value => mismatch(DiagnosticType::Bool, &value),
+        }
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Num(self)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Num(num) => Ok(num),
+            value => mismatch(DiagnosticType::Num, &value),
+        }
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Str(str) => Ok(str.to_string()),
+            value => mismatch(DiagnosticType::Str, &value),
+        }
+    }
+}
+
+impl FromValue for Rc<str> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Str(str) => Ok(str),
+            value => mismatch(DiagnosticType::Str, &value),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::List(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::List(list) => list.iter().cloned().map(T::from_value).collect(),
+            value => mismatch(DiagnosticType::List, &value),
+        }
+    }
+}
+
+impl IntoValue for PVec<Value> {
+    fn into_value(self) -> Value {
+        Value::List(self)
+    }
+}
+
+/// `None` is `nil`.
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Nil, IntoValue::into_value)
+    }
+}
+
+/// `nil` is `None`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
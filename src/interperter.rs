@@ -1,15 +1,23 @@
 mod env;
+mod native;
 mod stdlib;
 
+use std::{collections::BTreeMap, rc::Rc};
+
 pub use env::Env;
-pub use stdlib::stub_stdlib;
+pub use native::{Ctx, NativeFn, NativeFunc};
+pub use stdlib::{native_funcs, stub_stdlib, type_stdlib};
 
 use crate::{
     ast::{
-        BinaryExpr, BinaryOp, Binding, BindingMetadata, Block, Call, ElseBlock, Expr, Identifier,
-        IfExpr, Literal, Program, Stmt, UnaryExpr, UnaryOp,
+        BinaryExpr, BinaryOp, Binding, BindingMetadata, Block, Call, ElseBlock, Expr, FieldAccess,
+        IdentLocation, Identifier, IfExpr, Import, Index, Interpolated, List, Literal,
+        LiteralValue, Match, Pattern, Program, Record, StackIndex, Stmt, TypeDecl, UnaryExpr,
+        UnaryOp, VariantInfo,
     },
-    lexer::Pos,
+    lexer::Span,
+    util::persistent_vec::PVec,
+    vm,
 };
 
 pub fn interpert(program: Program, env: &mut Env) -> Result<Value> {
@@ -22,12 +30,14 @@ trait Evaluate {
     fn evaluate(&self, env: &mut Env) -> Result<Value>;
 }
 
+/// The value of the last statement, if it is an expression.
 impl Evaluate for Program {
     fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let mut value = Value::Nil;
         for stmt in self {
-            stmt.evaluate(env)?;
+            value = stmt.evaluate(env)?;
         }
-        Ok(Value::Nil)
+        Ok(value)
     }
 }
 
@@ -35,6 +45,8 @@ impl Evaluate for Stmt {
     fn evaluate(&self, env: &mut Env) -> Result<Value> {
         match self {
             Stmt::Let(binding) => binding.evaluate(env),
+            Stmt::Type(type_decl) => type_decl.evaluate(env),
+            Stmt::Import(import) => import.evaluate(env),
             Stmt::Expr(expr) => expr.evaluate(env),
         }
     }
@@ -47,6 +59,7 @@ impl Evaluate for Binding {
             BindingMetadata::Func {
                 arguments,
                 upvalues,
+                ..
             } => {
                 let arguments = arguments.clone();
                 let upvalues = upvalues
@@ -54,17 +67,50 @@ impl Evaluate for Binding {
                     .map(|upvalue| env.resolve_upvalue(upvalue.clone()).clone())
                     .collect();
                 let body = self.value.clone();
-                Value::Func(Func::User(UserFunc {
-                    name: self.ident.name.clone(),
+                let Pattern::Identifier(ident) = &self.pattern else {
+                    unreachable!("parser should only make named functions");
+                };
+                Value::Func(Func::User(Rc::new(UserFunc {
+                    name: ident.name.clone(),
                     arguments,
                     upvalues,
                     body,
-                }))
+                })))
             }
             // But do for a variable
             BindingMetadata::Var => self.value.evaluate(env)?,
         };
-        env.define(value);
+        bind_pattern(&self.pattern, value, env)?;
+        Ok(Value::Nil)
+    }
+}
+
+impl Evaluate for Import {
+    fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let module = &self.module;
+        let exports = match env.module_exports(&module.path) {
+            Some(exports) => exports.clone(),
+            None => {
+                let mut module_env = env.module_env();
+                let exports = module
+                    .program
+                    .evaluate(&mut module_env)
+                    .and_then(|_| module.exports.evaluate(&mut module_env));
+                env.end_module_env(module_env);
+
+                let exports = exports.map_err(|error| {
+                    Error::new(ErrorKind::InModule {
+                        name: module.name.clone(),
+                        source: module.source.clone(),
+                        error: Box::new(error),
+                    })
+                    .span(self.span)
+                })?;
+                env.cache_module(module.path.clone(), exports.clone());
+                exports
+            }
+        };
+        bind_pattern(&self.pattern, exports, env)?;
         Ok(Value::Nil)
     }
 }
@@ -75,9 +121,15 @@ impl Evaluate for Expr {
             Expr::Block(block) => block.evaluate(env),
             Expr::Call(call) => call.evaluate(env),
             Expr::If(if_expr) => if_expr.evaluate(env),
+            Expr::Match(match_expr) => match_expr.evaluate(env),
             Expr::Binary(binary_expr) => binary_expr.evaluate(env),
             Expr::Unary(unary_expr) => unary_expr.evaluate(env),
             Expr::Literal(literal) => literal.evaluate(env),
+            Expr::Interpolated(interpolated) => interpolated.evaluate(env),
+            Expr::List(list) => list.evaluate(env),
+            Expr::Index(index) => index.evaluate(env),
+            Expr::Record(record) => record.evaluate(env),
+            Expr::Field(field_access) => field_access.evaluate(env),
             Expr::Identifier(identifier) => identifier.evaluate(env),
         }
     }
@@ -85,14 +137,23 @@ impl Evaluate for Expr {
 
 impl Evaluate for Block {
     fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let locals_len = env.locals_len();
         for stmt in &self.stmts {
             stmt.evaluate(env)?;
         }
 
-        self.return_expr
+        let value = self
+            .return_expr
             .as_ref()
             .map(|e| e.evaluate(env))
-            .unwrap_or(Ok(Value::Nil))
+            .unwrap_or(Ok(Value::Nil))?;
+
+        // The block's locals go out of scope. But a tail call has already
+        // replaced the whole frame, so leave it alone.
+        if !matches!(value, Value::TailCall) {
+            env.truncate_locals(locals_len);
+        }
+        Ok(value)
     }
 }
 
@@ -105,20 +166,9 @@ impl Evaluate for Call {
                 expected: DiagnosticType::Func,
                 actual: func.into(),
             })
-            .pos(self.pos));
+            .span(self.target.span()));
         };
 
-        if let Func::User(func) = func.clone() {
-            let incorrect_arity = func.arguments.len() > self.arguments.len();
-            if incorrect_arity {
-                return Err(Error::new(ErrorKind::IncorrectArity {
-                    given: self.arguments.len(),
-                    correct: func.arguments.len(),
-                })
-                .pos(self.pos));
-            }
-        }
-
         let arguments: Vec<Value> = self
             .arguments
             .iter()
@@ -126,31 +176,87 @@ impl Evaluate for Call {
             .collect::<Result<_>>()?;
 
         // Don't tail call for native funcs b/c they handle args differently
-        if self.is_tail_call && matches!(func, Func::User(_)) {
-            env.tail_call(func, arguments);
-            return Ok(Value::TailCall);
+        if self.is_tail_call {
+            if let Func::User(user_func) = &func {
+                Arity::exact(user_func.arguments.len())
+                    .check(arguments.len())
+                    .map_err(|e| e.span(self.span))?;
+                env.tail_call(func, arguments);
+                return Ok(Value::TailCall);
+            }
         }
 
-        // Create new frame *after* evaluating arguments
-        let mut env = env.new_frame(func.clone());
+        call_func(func, arguments, Some(self.span), env)
+    }
+}
+
+/// Calls `func`, like from Rust code (without a call site) or from a `Call`.
+pub fn call(func: Func, arguments: Vec<Value>, env: &mut Env) -> Result<Value> {
+    call_func(func, arguments, None, env)
+}
 
-        // Actually define arguments
-        for arg in arguments.clone() {
-            env.define(arg);
+fn call_func(
+    func: Func,
+    arguments: Vec<Value>,
+    call_site: Option<Span>,
+    env: &mut Env,
+) -> Result<Value> {
+    let at_call_site = |e: Error| match call_site {
+        Some(span) => e.span(span),
+        None => e,
+    };
+
+    match &func {
+        Func::User(user_func) => Arity::exact(user_func.arguments.len()),
+        Func::Native(native_func) => native_func.arity,
+        Func::Constructor(info) => Arity::exact(info.arity.unwrap_or(0)),
+        Func::Compiled(_) => {
+            return Err(at_call_site(Error::new(ErrorKind::ForeignFunc {
+                func: Value::Func(func.clone()).to_string(),
+            })))
+        }
+    }
+    .check(arguments.len())
+    .map_err(at_call_site)?;
+
+    let func = match func {
+        Func::User(func) => func,
+        // native funcs don't support tce
+        Func::Native(func) => {
+            return func
+                .call(&mut Ctx::interpreter(env), arguments)
+                .map_err(at_call_site)
         }
+        Func::Constructor(info) => return construct(&info, arguments).map_err(at_call_site),
+        func @ Func::Compiled(_) => {
+            return Err(at_call_site(Error::new(ErrorKind::ForeignFunc {
+                func: Value::Func(func).to_string(),
+            })))
+        }
+    };
 
-        loop {
-            let ret = match env.func().clone() {
-                Func::User(func) => func.body.evaluate(&mut env)?,
-                // native funcs don't support tce
-                Func::Native(func) => break func.call(arguments),
-            };
+    // Create new frame *after* evaluating arguments
+    let mut env = env.new_frame(Func::User(func), call_site);
+    for arg in arguments {
+        env.define(arg);
+    }
 
-            if let Value::TailCall = ret {
-                continue;
-            } else {
-                break Ok(ret);
-            }
+    loop {
+        let Func::User(func) = env.func().clone() else {
+            unreachable!("only user funcs are tail called");
+        };
+        let ret = func
+            .bind_arguments(&mut env)
+            .and_then(|()| func.body.evaluate(&mut env))
+            .map_err(|e| match env.trace_frame() {
+                Some(frame) => e.in_frame(frame),
+                None => e,
+            })?;
+
+        if let Value::TailCall = ret {
+            continue;
+        } else {
+            break Ok(ret);
         }
     }
 }
@@ -170,13 +276,69 @@ impl Evaluate for IfExpr {
     }
 }
 
+impl Evaluate for TypeDecl {
+    fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        // Defined in the same order that the parser declared them
+        for variant in &self.variants {
+            let info = &variant.info;
+            env.define(match info.arity {
+                Some(_) => Value::Func(Func::Constructor(info.clone())),
+                None => Value::Variant(Variant {
+                    type_name: info.type_name.clone(),
+                    name: info.name.clone(),
+                    fields: Vec::new(),
+                }),
+            });
+        }
+        Ok(Value::Nil)
+    }
+}
+
+impl Evaluate for Match {
+    fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let value = self.value.evaluate(env)?;
+        for arm in &self.arms {
+            let mut bound = Vec::new();
+            if !arm.pattern.destructure(&value, &mut bound) {
+                continue;
+            }
+
+            let locals_len = env.locals_len();
+            for value in bound {
+                env.define(value);
+            }
+
+            if let Some(guard) = &arm.guard {
+                if !guard.evaluate(env)?.is_truthy() {
+                    env.truncate_locals(locals_len);
+                    continue;
+                }
+            }
+
+            let value = arm.body.evaluate(env)?;
+            // Just like a block, unless it was a tail call
+            if !matches!(value, Value::TailCall) {
+                env.truncate_locals(locals_len);
+            }
+            return Ok(value);
+        }
+
+        Err(Error::new(ErrorKind::NoMatchingArm {
+            value: value.to_string(),
+        })
+        .span(self.value.span()))
+    }
+}
+
 impl Evaluate for BinaryExpr {
     fn evaluate(&self, env: &mut Env) -> Result<Value> {
-        use Value::{Bool, Num, Str};
+        use Value::{Bool, Num};
 
         let lhs = self.lhs.evaluate(env)?;
         // A closure so that it is lazy, for short-circuiting
         let mut rhs = || self.rhs.evaluate(env);
+        let (lhs_span, rhs_span) = (self.lhs.span(), self.rhs.span());
+        let num = |value: Value, span: Span| value.as_num().map_err(|e| e.span(span));
         Ok(match &self.op {
             BinaryOp::Or => {
                 if lhs.is_truthy() {
@@ -194,56 +356,159 @@ impl Evaluate for BinaryExpr {
             }
             BinaryOp::NotEq => Bool(lhs != rhs()?),
             BinaryOp::Eq => Bool(lhs == rhs()?),
-            BinaryOp::Greater => Bool(lhs.as_num()? > rhs()?.as_num()?),
-            BinaryOp::GreaterEq => Bool(lhs.as_num()? >= rhs()?.as_num()?),
-            BinaryOp::Less => Bool(lhs.as_num()? < rhs()?.as_num()?),
-            BinaryOp::LessEq => Bool(lhs.as_num()? <= rhs()?.as_num()?),
-            BinaryOp::Subtract => Num(lhs.as_num()? - rhs()?.as_num()?),
-            BinaryOp::Add => match (lhs, rhs()?) {
-                (Num(a), Num(b)) => Num(a + b),
-                (Str(a), Num(b)) => Str(a + &b.to_string()),
-                (Num(a), Str(b)) => Str(a.to_string() + &b),
-                (Str(a), Str(b)) => Str(a + &b),
-
-                // Errors:
-                (Num(_), b) | (Str(_), b) => {
-                    return Err(Error::new(ErrorKind::TypeError {
-                        expected: DiagnosticType::Num,
-                        actual: b.into(),
-                    })
-                    .pos(self.op_pos))
-                }
-                (a, _) => {
-                    return Err(Error::new(ErrorKind::TypeError {
-                        expected: DiagnosticType::Num,
-                        actual: a.into(),
-                    })
-                    .pos(self.op_pos))
-                }
-            },
-            BinaryOp::Divide => Num(lhs.as_num()? / rhs()?.as_num()?),
-            BinaryOp::Multiply => Num(lhs.as_num()? * rhs()?.as_num()?),
+            BinaryOp::Greater => Bool(num(lhs, lhs_span)? > num(rhs()?, rhs_span)?),
+            BinaryOp::GreaterEq => Bool(num(lhs, lhs_span)? >= num(rhs()?, rhs_span)?),
+            BinaryOp::Less => Bool(num(lhs, lhs_span)? < num(rhs()?, rhs_span)?),
+            BinaryOp::LessEq => Bool(num(lhs, lhs_span)? <= num(rhs()?, rhs_span)?),
+            BinaryOp::Subtract => Num(num(lhs, lhs_span)? - num(rhs()?, rhs_span)?),
+            BinaryOp::Add => add(lhs, rhs()?, lhs_span, rhs_span)?,
+            BinaryOp::Divide => Num(num(lhs, lhs_span)? / num(rhs()?, rhs_span)?),
+            BinaryOp::Multiply => Num(num(lhs, lhs_span)? * num(rhs()?, rhs_span)?),
         })
     }
 }
 
+/// Adds numbers, or concatenates strings (converting a number added to one).
+pub(crate) fn add(lhs: Value, rhs: Value, lhs_span: Span, rhs_span: Span) -> Result<Value> {
+    use Value::{Num, Str};
+
+    Ok(match (lhs, rhs) {
+        (Num(a), Num(b)) => Num(a + b),
+        (Str(a), Num(b)) => Str(format!("{a}{b}").into()),
+        (Num(a), Str(b)) => Str(format!("{a}{b}").into()),
+        (Str(a), Str(b)) => Str(format!("{a}{b}").into()),
+
+        // Errors:
+        (Num(_), b) | (Str(_), b) => {
+            return Err(Error::new(ErrorKind::TypeError {
+                expected: DiagnosticType::Num,
+                actual: b.into(),
+            })
+            .span(rhs_span))
+        }
+        (a, _) => {
+            return Err(Error::new(ErrorKind::TypeError {
+                expected: DiagnosticType::Num,
+                actual: a.into(),
+            })
+            .span(lhs_span))
+        }
+    })
+}
+
 impl Evaluate for UnaryExpr {
     fn evaluate(&self, env: &mut Env) -> Result<Value> {
         let rhs = self.rhs.evaluate(env)?;
         match self.op {
             UnaryOp::Not => Ok(Value::Bool(!rhs.is_truthy())),
-            UnaryOp::Negate => Ok(Value::Num(-rhs.as_num()?)),
+            UnaryOp::Negate => Ok(Value::Num(
+                -rhs.as_num().map_err(|e| e.span(self.rhs.span()))?,
+            )),
         }
     }
 }
 
 impl Evaluate for Literal {
     fn evaluate(&self, _env: &mut Env) -> Result<Value> {
-        Ok(match self {
-            Literal::Bool(b) => Value::Bool(*b),
-            Literal::Number(n) => Value::Num(*n),
-            Literal::Str(s) => Value::Str(s.clone()),
-            Literal::Nil => Value::Nil,
+        Ok(Value::from(&self.value))
+    }
+}
+
+impl Evaluate for Interpolated {
+    fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let mut s = String::new();
+        for part in &self.parts {
+            // Same formatting as `print`
+            s += &part.evaluate(env)?.to_string();
+        }
+        Ok(Value::Str(s.into()))
+    }
+}
+
+impl Evaluate for List {
+    fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let items = self
+            .items
+            .iter()
+            .map(|item| item.evaluate(env))
+            .collect::<Result<_>>()?;
+        Ok(Value::List(items))
+    }
+}
+
+impl Evaluate for Index {
+    fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let list = self.target.evaluate(env)?;
+        let list = list.as_list().map_err(|e| e.span(self.target.span()))?;
+        let index = self.index.evaluate(env)?;
+        let index = index.as_num().map_err(|e| e.span(self.index.span()))?;
+        get_index(&list, index).map_err(|e| e.span(self.span))
+    }
+}
+
+/// The item at `index`, which must be a whole number in bounds.
+pub(crate) fn get_index(list: &PVec<Value>, index: f64) -> Result<Value> {
+    let is_whole = index >= 0.0 && index.fract() == 0.0;
+    let item = if is_whole {
+        list.get(index as usize)
+    } else {
+        None
+    };
+    item.cloned().ok_or_else(|| {
+        Error::new(ErrorKind::IndexOutOfBounds {
+            index,
+            len: list.len(),
+        })
+    })
+}
+
+pub(crate) fn get_index_mut(list: &mut PVec<Value>, index: f64) -> Result<&mut Value> {
+    let len = list.len();
+    let is_whole = index >= 0.0 && index.fract() == 0.0;
+    let item = if is_whole {
+        list.get_mut(index as usize)
+    } else {
+        None
+    };
+    item.ok_or_else(|| Error::new(ErrorKind::IndexOutOfBounds { index, len }))
+}
+
+impl Evaluate for Record {
+    fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let mut record = match &self.base {
+            Some(base) => base
+                .evaluate(env)?
+                .into_record()
+                .map_err(|e| e.span(base.span()))?,
+            None => Rc::default(),
+        };
+        for field in &self.fields {
+            let value = field.value.evaluate(env)?;
+            // Updates can't add new fields
+            if self.base.is_some() && !record.contains_key(&field.name) {
+                return Err(Error::new(ErrorKind::NoSuchField {
+                    field: field.name.clone(),
+                })
+                .span(field.span));
+            }
+            // Only copies the fields if the base is used elsewhere
+            Rc::make_mut(&mut record).insert(field.name.clone(), value);
+        }
+        Ok(Value::Record(record))
+    }
+}
+
+impl Evaluate for FieldAccess {
+    fn evaluate(&self, env: &mut Env) -> Result<Value> {
+        let target = self.target.evaluate(env)?;
+        let record = target
+            .as_record()
+            .map_err(|e| e.span(self.target.span()))?;
+        record.get(&self.field).cloned().ok_or_else(|| {
+            Error::new(ErrorKind::NoSuchField {
+                field: self.field.clone(),
+            })
+            .span(self.span)
         })
     }
 }
@@ -256,13 +521,91 @@ impl Evaluate for Identifier {
     }
 }
 
+/// Destructures `value` with `pattern`, and defines the names it binds.
+fn bind_pattern(pattern: &Pattern, value: Value, env: &mut Env) -> Result<()> {
+    let mut bound = Vec::new();
+    if !pattern.destructure(&value, &mut bound) {
+        return Err(Error::new(ErrorKind::PatternMismatch {
+            value: value.to_string(),
+        })
+        .span(pattern.span()));
+    }
+    for value in bound {
+        env.define(value);
+    }
+    Ok(())
+}
+
+pub(crate) trait Destructure {
+    /// Whether `value` matches. If it does, the values of the names bound
+    /// are pushed to `bound`, in the same order that the parser declared
+    /// them.
+    fn destructure(&self, value: &Value, bound: &mut Vec<Value>) -> bool;
+}
+
+impl Destructure for Pattern {
+    fn destructure(&self, value: &Value, bound: &mut Vec<Value>) -> bool {
+        match (self, value) {
+            (Pattern::Identifier(_), value) => {
+                bound.push(value.clone());
+                true
+            }
+            (Pattern::Wildcard(_), _) => true,
+            (Pattern::Literal(literal), value) => Value::from(&literal.value) == *value,
+            (Pattern::List(pattern), Value::List(list)) => {
+                let len_matches = match pattern.rest {
+                    Some(_) => list.len() >= pattern.items.len(),
+                    None => list.len() == pattern.items.len(),
+                };
+                if !len_matches {
+                    return false;
+                }
+
+                let items_match = pattern
+                    .items
+                    .iter()
+                    .zip(list.iter())
+                    .all(|(pattern, item)| pattern.destructure(item, bound));
+                match &pattern.rest {
+                    Some(rest) if items_match => {
+                        let rest_items = list.iter().skip(pattern.items.len()).cloned().collect();
+                        rest.destructure(&Value::List(rest_items), bound)
+                    }
+                    _ => items_match,
+                }
+            }
+            (Pattern::Record(pattern), Value::Record(record)) => {
+                pattern.fields.iter().all(|field| {
+                    record
+                        .get(&field.name)
+                        .is_some_and(|value| field.pattern.destructure(value, bound))
+                })
+            }
+            (Pattern::Variant(pattern), Value::Variant(variant)) => {
+                variant.type_name == pattern.info.type_name
+                    && variant.name == pattern.info.name
+                    && pattern
+                        .fields
+                        .iter()
+                        .zip(&variant.fields)
+                        .all(|(pattern, field)| pattern.destructure(field, bound))
+            }
+            (Pattern::List(_) | Pattern::Record(_) | Pattern::Variant(_), _) => false,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Value {
     Bool(bool),
     Num(f64),
-    Str(String),
+    Str(Rc<str>),
     Func(Func),
-    List(Vec<Value>),
+    /// Persistent, so that "changing" an item doesn't copy the whole list.
+    List(PVec<Value>),
+    /// Shared, so that reading a field doesn't copy the whole record.
+    Record(Rc<BTreeMap<String, Value>>),
+    Variant(Variant),
     Nil,
 
     /// Indicates that a tail call should be performed
@@ -270,14 +613,14 @@ pub enum Value {
 }
 
 impl Value {
-    fn is_truthy(&self) -> bool {
+    pub(crate) fn is_truthy(&self) -> bool {
         !self.is_falsy()
     }
     fn is_falsy(&self) -> bool {
         matches!(self, Value::Bool(false) | Value::Nil)
     }
 
-    fn as_num(&self) -> Result<f64> {
+    pub(crate) fn as_num(&self) -> Result<f64> {
         match self {
             Self::Num(n) => Ok(*n),
             _ => Err(Error::new(ErrorKind::TypeError {
@@ -287,7 +630,7 @@ impl Value {
         }
     }
 
-    fn as_str(&self) -> Result<String> {
+    fn as_str(&self) -> Result<Rc<str>> {
         match self {
             Self::Str(s) => Ok(s.clone()),
             _ => Err(Error::new(ErrorKind::TypeError {
@@ -297,7 +640,29 @@ impl Value {
         }
     }
 
-    fn as_list(&self) -> Result<Vec<Value>> {
+    pub(crate) fn as_record(&self) -> Result<&BTreeMap<String, Value>> {
+        match self {
+            Self::Record(r) => Ok(r),
+            _ => Err(Error::new(ErrorKind::TypeError {
+                expected: DiagnosticType::Record,
+                actual: DiagnosticType::from(self),
+            })),
+        }
+    }
+
+    /// The record itself, so that it can be updated without a copy if
+    /// nothing else shares it.
+    pub(crate) fn into_record(self) -> Result<Rc<BTreeMap<String, Value>>> {
+        match self {
+            Self::Record(r) => Ok(r),
+            _ => Err(Error::new(ErrorKind::TypeError {
+                expected: DiagnosticType::Record,
+                actual: DiagnosticType::from(&self),
+            })),
+        }
+    }
+
+    pub(crate) fn as_list(&self) -> Result<PVec<Value>> {
         match self {
             Self::List(l) => Ok(l.clone()),
             _ => Err(Error::new(ErrorKind::TypeError {
@@ -308,6 +673,82 @@ impl Value {
     }
 }
 
+impl From<&LiteralValue> for Value {
+    fn from(literal: &LiteralValue) -> Self {
+        match literal {
+            LiteralValue::Bool(b) => Value::Bool(*b),
+            LiteralValue::Number(n) => Value::Num(*n),
+            LiteralValue::Str(s) => Value::Str(s.as_str().into()),
+            LiteralValue::Nil => Value::Nil,
+        }
+    }
+}
+
+/// How values are shown by `print` and in interpolated strings.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Bool(bool) => write!(f, "{bool}"),
+            Value::Num(num) => write!(f, "{num}"),
+            Value::Str(str) => write!(f, "{str}"),
+            Value::Func(func) => match func {
+                Func::User(user_func) => write!(f, "<fn {}>", user_func.name),
+                Func::Native(native_func) => write!(f, "<native fn {}>", native_func.name),
+                Func::Constructor(info) => write!(f, "<constructor {}>", info.name),
+                Func::Compiled(closure) => write!(f, "<fn {}>", closure.proto.name),
+            },
+            Value::List(list) => {
+                write!(f, "[")?;
+                for (i, item) in list.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_nested(f)?;
+                }
+                write!(f, "]")
+            }
+            Value::Record(record) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in record.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{name}: ")?;
+                    value.fmt_nested(f)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Variant(variant) => {
+                write!(f, "{}", variant.name)?;
+                if variant.fields.is_empty() {
+                    return Ok(());
+                }
+                write!(f, "(")?;
+                for (i, field) in variant.fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    field.fmt_nested(f)?;
+                }
+                write!(f, ")")
+            }
+            Value::Nil => write!(f, "nil"),
+            Value::TailCall => write!(f, "<tail call marker>"),
+        }
+    }
+}
+
+impl Value {
+    /// Formats a value inside a list or record. Strings are quoted, so that
+    /// `["a, b"]` and `["a", "b"]` can be told apart.
+    fn fmt_nested(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Str(str) => write!(f, "{str:?}"),
+            value => write!(f, "{value}"),
+        }
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -316,48 +757,152 @@ impl PartialEq for Value {
             (Self::Str(l), Self::Str(r)) => l == r,
             (Self::Func(_), Self::Func(_)) => false,
             (Self::List(l), Self::List(r)) => l == r,
+            (Self::Record(l), Self::Record(r)) => l == r,
+            (Self::Variant(l), Self::Variant(r)) => l == r,
             (Self::Nil, Self::Nil) => true,
             _ => false,
         }
     }
 }
 
+/// A value of a `type`, made by one of its variants.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variant {
+    pub(crate) type_name: String,
+    pub(crate) name: String,
+    pub(crate) fields: Vec<Value>,
+}
+
 #[derive(Clone, Debug)]
 pub enum Func {
-    User(UserFunc),
-    Native(&'static dyn NativeFunc),
+    User(Rc<UserFunc>),
+    Native(Rc<NativeFunc>),
+    /// Makes a variant with fields.
+    Constructor(VariantInfo),
+    /// A function compiled to bytecode, which only the VM can call.
+    Compiled(Rc<vm::Closure>),
+}
+
+pub(crate) fn construct(info: &VariantInfo, fields: Vec<Value>) -> Result<Value> {
+    Arity::exact(info.arity.unwrap_or(0)).check(fields.len())?;
+    Ok(Value::Variant(Variant {
+        type_name: info.type_name.clone(),
+        name: info.name.clone(),
+        fields,
+    }))
 }
 
 #[derive(Clone, Debug)]
 pub struct UserFunc {
     name: String,
-    arguments: Vec<Identifier>,
+    arguments: Vec<Pattern>,
     upvalues: Vec<Value>,
-    body: Expr,
+    body: Rc<Expr>,
+}
+
+impl UserFunc {
+    /// Destructures the arguments that aren't just names. Each argument is
+    /// already defined in its own slot, so the names go after them.
+    fn bind_arguments(&self, env: &mut Env) -> Result<()> {
+        for (i, pattern) in self.arguments.iter().enumerate() {
+            if matches!(pattern, Pattern::Identifier(_)) {
+                continue;
+            }
+            // Slot 0 is the function itself
+            let value = env.get(IdentLocation::Stack(StackIndex(i + 1))).clone();
+            bind_pattern(pattern, value, env)?;
+        }
+        Ok(())
+    }
 }
 
-pub trait NativeFunc: std::fmt::Debug {
-    fn call(&self, arguments: Vec<Value>) -> Result<Value>;
+/// How many arguments a function takes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    /// `None` if there is no limit.
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub const fn exact(n: usize) -> Self {
+        Arity {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    pub const fn range(min: usize, max: usize) -> Self {
+        Arity {
+            min,
+            max: Some(max),
+        }
+    }
+
+    pub const fn at_least(min: usize) -> Self {
+        Arity { min, max: None }
+    }
+
+    pub fn accepts(self, given: usize) -> bool {
+        given >= self.min && self.max.is_none_or(|max| given <= max)
+    }
+
+    /// Errors unless `given` arguments are accepted.
+    pub fn check(self, given: usize) -> Result<()> {
+        if self.accepts(given) {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::IncorrectArity {
+                given,
+                correct: self,
+            }))
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Error {
     pub kind: ErrorKind,
-    pub pos: Option<Pos>,
+    pub span: Option<Span>,
+    /// The calls that were being made when the error happened, innermost
+    /// first.
+    pub trace: Vec<TraceFrame>,
 }
 
 impl Error {
     pub fn new(kind: ErrorKind) -> Self {
-        Self { kind, pos: None }
+        Self {
+            kind,
+            span: None,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Attach a span to the error, unless it already has a (more precise)
+    /// one.
+    pub fn span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
     }
 
-    pub fn pos(mut self, pos: Pos) -> Self {
-        self.pos = Some(pos);
+    /// Record that the error passed through a call, on its way out.
+    pub fn in_frame(mut self, frame: TraceFrame) -> Self {
+        self.trace.push(frame);
         self
     }
 }
 
-#[expect(dead_code, reason = "Pretty error printing not implemented yet")]
+/// A call that was being made when an error happened.
+#[derive(Clone, Debug)]
+pub struct TraceFrame {
+    /// The function that was running. After tail calls, this isn't always
+    /// the one that was called.
+    pub name: String,
+    pub call_site: Span,
+    /// How many tail calls replaced the frame. They aren't in the trace.
+    pub tail_calls: usize,
+}
+
 #[derive(Debug)]
 pub enum ErrorKind {
     TypeError {
@@ -367,7 +912,37 @@ pub enum ErrorKind {
     IOError(std::io::Error),
     IncorrectArity {
         given: usize,
-        correct: usize,
+        correct: Arity,
+    },
+    IndexOutOfBounds {
+        index: f64,
+        len: usize,
+    },
+    /// A native function was called without an argument it needs.
+    MissingArgument {
+        index: usize,
+    },
+    /// An error from a function registered by the host.
+    Host(String),
+    /// A function made by an engine with the other runtime (e.g. a VM
+    /// function passed to an interpreter), which this one can't run.
+    ForeignFunc {
+        func: String,
+    },
+    NoSuchField {
+        field: String,
+    },
+    PatternMismatch {
+        value: String,
+    },
+    NoMatchingArm {
+        value: String,
+    },
+    /// An error while running an imported module.
+    InModule {
+        name: String,
+        source: String,
+        error: Box<Error>,
     },
 }
 
@@ -378,6 +953,8 @@ pub enum DiagnosticType {
     Str,
     Func,
     List,
+    Record,
+    Variant,
     Nil,
     TailCall,
 }
@@ -389,6 +966,8 @@ impl From<&Value> for DiagnosticType {
             Value::Str(_) => Self::Str,
             Value::Func(_) => Self::Func,
             Value::List(_) => Self::List,
+            Value::Record(_) => Self::Record,
+            Value::Variant(_) => Self::Variant,
             Value::Nil => Self::Nil,
             Value::TailCall => Self::TailCall,
         }
@@ -1,12 +1,24 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+};
 
-use crate::ast::{IdentLocation, StackIndex, Upvalue, UpvalueIndex};
+use crate::{
+    ast::{IdentLocation, StackIndex, Upvalue, UpvalueIndex},
+    lexer::Span,
+};
 
-use super::{Func, Value};
+use super::{Func, TraceFrame, Value};
 
+#[derive(Clone)]
 pub struct Env {
     locals_stack: Vec<Value>,
     call_frames: Vec<CallFrame>,
+    /// The exports of every module that has been run, by canonical path.
+    modules: HashMap<PathBuf, Value>,
+    /// Functions the host registered, which modules can call too.
+    host: Vec<Value>,
 }
 
 impl Env {
@@ -14,8 +26,12 @@ impl Env {
         let mut env = Env {
             locals_stack: Vec::new(),
             call_frames: Vec::new(),
+            modules: HashMap::new(),
+            host: Vec::new(),
         };
-        super::stdlib::define_stdlib(&mut env);
+        for func in super::stdlib::native_funcs() {
+            env.define(func);
+        }
         env
     }
 
@@ -72,12 +88,53 @@ impl Env {
         &func.upvalues[i]
     }
 
+    pub fn module_exports(&self, path: &Path) -> Option<&Value> {
+        self.modules.get(path)
+    }
+
+    /// A fresh env to run a module in. It shares the modules that have
+    /// already been run, and must be given back with `end_module_env`.
+    pub fn module_env(&mut self) -> Env {
+        let mut env = Env::new();
+        for value in &self.host {
+            env.define_host(value.clone());
+        }
+        env.modules = std::mem::take(&mut self.modules);
+        env
+    }
+
+    pub fn end_module_env(&mut self, module_env: Env) {
+        self.modules = module_env.modules;
+    }
+
+    pub fn cache_module(&mut self, path: PathBuf, exports: Value) {
+        self.modules.insert(path, exports);
+    }
+
     pub fn define(&mut self, value: Value) {
         self.locals_stack.push(value);
     }
 
-    pub fn new_frame(&mut self, func: Func) -> FrameGuard {
-        FrameGuard::new(self, func)
+    /// Defines a function the host registered, which imported modules can
+    /// call too.
+    pub fn define_host(&mut self, value: Value) {
+        self.define(value.clone());
+        self.host.push(value);
+    }
+
+    /// How many locals are defined, in all frames.
+    pub fn locals_len(&self) -> usize {
+        self.locals_stack.len()
+    }
+
+    /// Undefine locals until there are only `len` left. Used when they go out
+    /// of scope.
+    pub fn truncate_locals(&mut self, len: usize) {
+        self.locals_stack.truncate(len);
+    }
+
+    pub fn new_frame(&mut self, func: Func, call_site: Option<Span>) -> FrameGuard<'_> {
+        FrameGuard::new(self, func, call_site)
     }
 
     pub fn tail_call(&mut self, func: Func, arguments: Vec<Value>) {
@@ -86,6 +143,7 @@ impl Env {
             .last_mut()
             .expect("tail call should be within call frame");
         frame.func = func.clone();
+        frame.tail_calls += 1;
 
         // clear stack up
         self.clear_call_frame_stack();
@@ -109,20 +167,32 @@ impl Env {
     }
 }
 
-#[derive(Debug)]
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct CallFrame {
     func: Func,
     stack_offset: usize,
+    /// `None` if it was called from Rust code.
+    call_site: Option<Span>,
+    /// How many times the frame has been reused for a tail call.
+    tail_calls: usize,
 }
 
 #[clippy::has_significant_drop]
 pub struct FrameGuard<'a>(&'a mut Env);
 impl<'a> FrameGuard<'a> {
-    fn new(env: &'a mut Env, func: Func) -> Self {
+    fn new(env: &'a mut Env, func: Func, call_site: Option<Span>) -> Self {
         let stack_offset = env.locals_stack.len();
         let call_frame = CallFrame {
             func: func.clone(),
             stack_offset,
+            call_site,
+            tail_calls: 0,
         };
         env.call_frames.push(call_frame);
 
@@ -139,6 +209,23 @@ impl<'a> FrameGuard<'a> {
             .expect("frame guard should have call frame")
             .func
     }
+
+    /// The frame, as it should be shown in a stack trace. Calls from Rust
+    /// code aren't shown.
+    pub fn trace_frame(&self) -> Option<TraceFrame> {
+        let frame = self
+            .call_frames
+            .last()
+            .expect("frame guard should have call frame");
+        let Func::User(func) = &frame.func else {
+            panic!("only user funcs are traced");
+        };
+        Some(TraceFrame {
+            name: func.name.clone(),
+            call_site: frame.call_site?,
+            tail_calls: frame.tail_calls,
+        })
+    }
 }
 impl Deref for FrameGuard<'_> {
     type Target = Env;
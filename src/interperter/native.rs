@@ -0,0 +1,93 @@
+use std::{fmt::Debug, rc::Rc};
+
+use super::{Arity, DiagnosticType, Env, Error, ErrorKind, Result, Value};
+use crate::{type_checker::Type, vm};
+
+/// The signature of a native function.
+pub type NativeFn = dyn Fn(&mut Ctx, Vec<Value>) -> Result<Value>;
+
+/// A function written in Rust, like those in the stdlib or registered with
+/// `Engine::register`.
+#[derive(Clone)]
+pub struct NativeFunc {
+    pub name: String,
+    /// Checked before `func` is called, so it can assume it.
+    pub arity: Arity,
+    /// What the type checker thinks it is. `Any` unless set with `ty`.
+    pub ty: Type,
+    pub func: Rc<NativeFn>,
+}
+
+impl NativeFunc {
+    pub fn new(
+        name: impl Into<String>,
+        arity: Arity,
+        func: impl Fn(&mut Ctx, Vec<Value>) -> Result<Value> + 'static,
+    ) -> Self {
+        NativeFunc {
+            name: name.into(),
+            arity,
+            ty: Type::Any,
+            func: Rc::new(func),
+        }
+    }
+
+    pub fn ty(mut self, ty: Type) -> Self {
+        self.ty = ty;
+        self
+    }
+
+    pub(crate) fn call(&self, ctx: &mut Ctx, arguments: Vec<Value>) -> Result<Value> {
+        self.arity.check(arguments.len())?;
+        (self.func)(ctx, arguments)
+    }
+}
+
+impl Debug for NativeFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunc")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a native function is given besides its arguments: the runtime that
+/// called it.
+pub struct Ctx<'a> {
+    runtime: Runtime<'a>,
+}
+
+enum Runtime<'a> {
+    Interpreter(&'a mut Env),
+    Vm(&'a mut vm::Env),
+}
+
+impl<'a> Ctx<'a> {
+    pub(crate) fn interpreter(env: &'a mut Env) -> Self {
+        Ctx {
+            runtime: Runtime::Interpreter(env),
+        }
+    }
+
+    pub(crate) fn vm(env: &'a mut vm::Env) -> Self {
+        Ctx {
+            runtime: Runtime::Vm(env),
+        }
+    }
+
+    /// Calls a function, like one that was passed to the native as an
+    /// argument.
+    pub fn call(&mut self, func: &Value, arguments: Vec<Value>) -> Result<Value> {
+        let Value::Func(func) = func else {
+            return Err(Error::new(ErrorKind::TypeError {
+                expected: DiagnosticType::Func,
+                actual: func.into(),
+            }));
+        };
+        match &mut self.runtime {
+            Runtime::Interpreter(env) => super::call(func.clone(), arguments, env),
+            Runtime::Vm(env) => vm::call(func.clone(), arguments, env),
+        }
+    }
+}
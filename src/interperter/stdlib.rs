@@ -1,160 +1,202 @@
-use std::io::Write;
+use std::{io::Write, rc::Rc};
 
-use super::{Error, ErrorKind, Func, NativeFunc, Value};
+use crate::util::persistent_vec::PVec;
+
+use super::{
+    get_index, get_index_mut, Arity, Ctx, Error, ErrorKind, Func, NativeFunc, Result, Value,
+};
+
+/// Every function in the stdlib. The parser, type checker, and runtimes all
+/// declare them from this, so they are always in the same order.
+///
+/// Functions that can fail (returning `nil`), or that take any number of
+/// arguments, return `Any`.
+pub fn natives() -> Vec<NativeFunc> {
+    use crate::type_checker::Type::{self, Any, Num, Str};
+
+    let func = |arguments: &[Type], result: Type| Type::Func(arguments.to_vec(), Box::new(result));
+    let list_of = |item: Type| Type::List(Box::new(item));
+    let native = |name, arity, ty, func: fn(Vec<Value>) -> Result<Value>| {
+        NativeFunc::new(name, arity, move |_, arguments| func(arguments)).ty(ty)
+    };
+
+    vec![
+        native("print", Arity::at_least(0), Any, print),
+        native("input", Arity::range(0, 1), Any, input),
+        native(
+            "num_from_str",
+            Arity::exact(1),
+            func(&[Str], Any),
+            num_from_str,
+        ),
+        native("list", Arity::at_least(0), Any, list),
+        native(
+            "list_get",
+            Arity::exact(2),
+            func(&[list_of(Any), Num], Any),
+            list_get,
+        ),
+        native(
+            "list_set",
+            Arity::exact(3),
+            func(&[list_of(Any), Num, Any], list_of(Any)),
+            list_set,
+        ),
+        native(
+            "list_push",
+            Arity::exact(2),
+            func(&[list_of(Any), Any], list_of(Any)),
+            list_push,
+        ),
+        native(
+            "list_len",
+            Arity::exact(1),
+            func(&[list_of(Any)], Num),
+            list_len,
+        ),
+        native(
+            "str_to_chars",
+            Arity::exact(1),
+            func(&[Str], list_of(Str)),
+            str_to_chars,
+        ),
+        native(
+            "str_from_chars",
+            Arity::exact(1),
+            func(&[list_of(Any)], Any),
+            str_from_chars,
+        ),
+        native("read_file", Arity::exact(1), func(&[Str], Any), read_file),
+        native(
+            "read_file_lines",
+            Arity::exact(1),
+            func(&[Str], Any),
+            read_file_lines,
+        ),
+        NativeFunc::new("map", Arity::exact(2), map)
+            .ty(func(&[list_of(Any), func(&[Any], Any)], list_of(Any))),
+        NativeFunc::new("filter", Arity::exact(2), filter)
+            .ty(func(&[list_of(Any), func(&[Any], Any)], list_of(Any))),
+        NativeFunc::new("fold", Arity::exact(3), fold)
+            .ty(func(&[list_of(Any), func(&[Any, Any], Any), Any], Any)),
+        NativeFunc::new("sort_by", Arity::exact(2), sort_by)
+            .ty(func(&[list_of(Any), func(&[Any, Any], Any)], list_of(Any))),
+        NativeFunc::new("find", Arity::exact(2), find)
+            .ty(func(&[list_of(Any), func(&[Any], Any)], Any)),
+    ]
+}
 
 pub fn stub_stdlib(env: &mut crate::parser::Env) {
-    macro_rules! stub {
-        ($func:ident) => {
-            env.declare_local(stringify!($func).to_string());
-        };
-        ($func:ident, $($funcs:ident),+) => {
-            stub!($func);
-            stub!($($funcs),+);
-        };
+    for native in natives() {
+        env.declare_func(native.name, native.arity);
     }
+}
 
-    stub!(
-        print,
-        input,
-        num_from_str,
-        list,
-        list_get,
-        list_set,
-        list_push,
-        list_len,
-        str_to_chars,
-        str_from_chars,
-        read_file,
-        read_file_lines
-    );
-}
-
-pub fn define_stdlib(env: &mut super::Env) {
-    macro_rules! define {
-        ($func:ident) => {{
-            #[derive(Debug)]
-            struct F;
-            impl NativeFunc for F {
-                fn call(&self, arguments: Vec<Value>) -> super::Result<Value> {
-                    $func(arguments)
-                }
-            }
-            env.define(Value::Func(Func::Native(&F)));
-        }};
-
-        ($func:ident, $($funcs:ident),+) => {
-            define!($func);
-            define!($($funcs),+);
-        }
+pub fn type_stdlib(env: &mut crate::type_checker::Env) {
+    for native in natives() {
+        env.declare(native.name, native.ty);
     }
+}
+
+/// The value of each function, in the same order that they are stubbed.
+pub fn native_funcs() -> Vec<Value> {
+    natives()
+        .into_iter()
+        .map(|native| Value::Func(Func::Native(Rc::new(native))))
+        .collect()
+}
 
-    define!(
-        print,
-        input,
-        num_from_str,
-        list,
-        list_get,
-        list_set,
-        list_push,
-        list_len,
-        str_to_chars,
-        str_from_chars,
-        read_file,
-        read_file_lines
-    );
-}
-
-fn print(arguments: Vec<Value>) -> super::Result<Value> {
+/// The argument at `index`, or an error if it wasn't given.
+fn argument(arguments: &[Value], index: usize) -> Result<&Value> {
+    arguments
+        .get(index)
+        .ok_or_else(|| Error::new(ErrorKind::MissingArgument { index }))
+}
+
+fn print(arguments: Vec<Value>) -> Result<Value> {
     for arg in arguments {
-        match arg {
-            Value::Bool(bool) => print!("{bool}"),
-            Value::Num(num) => print!("{num}"),
-            Value::Str(str) => print!("{str}"),
-            Value::Func(f) => match f {
-                Func::User(user_func) => print!("<fn {}>", user_func.name),
-                Func::Native(native_func) => print!("<native fn {:?}>", native_func),
-            },
-            Value::List(vec) => print!("{vec:?}"),
-            Value::Nil => print!("nil"),
-            Value::TailCall => {
-                print!("<tail call marker>");
-                unreachable!("tail call marker should never be assigned to anything");
-            }
-        };
+        print!("{arg}");
+        if let Value::TailCall = arg {
+            unreachable!("tail call marker should never be assigned to anything");
+        }
         print!(" ");
     }
     println!();
     Ok(Value::Nil)
 }
 
-fn input(arguments: Vec<Value>) -> super::Result<Value> {
-    if arguments.len() > 1 {
-        return Err(Error::new(ErrorKind::IncorrectArity {
-            given: arguments.len(),
-            correct: 1,
-        }));
-    }
-    let empty_string = Value::Str("".to_string());
+fn input(arguments: Vec<Value>) -> Result<Value> {
+    let empty_string = Value::Str("".into());
     let question_str = arguments.first().unwrap_or(&empty_string);
-    print!("{question_str:?}");
-    std::io::stdout().flush().unwrap();
+    print!("{question_str}");
+    std::io::stdout()
+        .flush()
+        .map_err(|err| Error::new(ErrorKind::IOError(err)))?;
 
     let mut input = String::new();
-    std::io::stdin()
+    let read = std::io::stdin()
         .read_line(&mut input)
         .map_err(|err| Error::new(ErrorKind::IOError(err)))?;
-    input.pop().expect("trailing newline should be removed");
-    Ok(Value::Str(input))
+    // Nothing left to read
+    if read == 0 {
+        return Ok(Value::Nil);
+    }
+    let line = input.strip_suffix('\n').unwrap_or(&input);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    Ok(Value::Str(line.into()))
 }
 
-fn num_from_str(arguments: Vec<Value>) -> super::Result<Value> {
-    let str = arguments.first().unwrap().as_str()?;
+fn num_from_str(arguments: Vec<Value>) -> Result<Value> {
+    let str = argument(&arguments, 0)?.as_str()?;
     match str.parse() {
         Ok(num) => Ok(Value::Num(num)),
         Err(_) => Ok(Value::Nil),
     }
 }
 
-fn list(arguments: Vec<Value>) -> super::Result<Value> {
-    Ok(Value::List(arguments))
+fn list(arguments: Vec<Value>) -> Result<Value> {
+    Ok(Value::List(arguments.into_iter().collect()))
 }
 
-fn list_get(arguments: Vec<Value>) -> super::Result<Value> {
-    let list = arguments.first().unwrap().as_list()?;
-    let index = arguments.get(1).unwrap().as_num()? as usize;
-    Ok(list.get(index).cloned().unwrap_or(Value::Nil))
+fn list_get(arguments: Vec<Value>) -> Result<Value> {
+    let list = argument(&arguments, 0)?.as_list()?;
+    let index = argument(&arguments, 1)?.as_num()?;
+    Ok(get_index(&list, index).unwrap_or(Value::Nil))
 }
 
-fn list_set(arguments: Vec<Value>) -> super::Result<Value> {
-    let mut list = arguments.first().unwrap().as_list()?;
-    let index = arguments.get(1).unwrap().as_num()? as usize;
-    let new_value = arguments.get(2).unwrap().clone();
-    *list.get_mut(index).unwrap() = new_value;
+fn list_set(arguments: Vec<Value>) -> Result<Value> {
+    let mut list = argument(&arguments, 0)?.as_list()?;
+    let index = argument(&arguments, 1)?.as_num()?;
+    let new_value = argument(&arguments, 2)?.clone();
+    *get_index_mut(&mut list, index)? = new_value;
     Ok(Value::List(list))
 }
 
-fn list_push(arguments: Vec<Value>) -> super::Result<Value> {
-    let mut list = arguments.first().unwrap().as_list()?;
-    let value = arguments.get(1).unwrap().clone();
+fn list_push(arguments: Vec<Value>) -> Result<Value> {
+    let mut list = argument(&arguments, 0)?.as_list()?;
+    let value = argument(&arguments, 1)?.clone();
     list.push(value);
     Ok(Value::List(list))
 }
 
-fn list_len(arguments: Vec<Value>) -> super::Result<Value> {
-    let list = arguments.first().unwrap().as_list()?;
+fn list_len(arguments: Vec<Value>) -> Result<Value> {
+    let list = argument(&arguments, 0)?.as_list()?;
     Ok(Value::Num(list.len() as f64))
 }
 
-fn str_to_chars(arguments: Vec<Value>) -> super::Result<Value> {
-    let str = arguments.first().unwrap().as_str()?;
-    let chars: Vec<_> = str.chars().map(|c| Value::Str(c.into())).collect();
+fn str_to_chars(arguments: Vec<Value>) -> Result<Value> {
+    let str = argument(&arguments, 0)?.as_str()?;
+    let chars = str
+        .chars()
+        .map(|c| Value::Str(c.to_string().into()))
+        .collect();
     Ok(Value::List(chars))
 }
 
-fn str_from_chars(arguments: Vec<Value>) -> super::Result<Value> {
-    let chars = arguments.first().unwrap().as_list()?;
+fn str_from_chars(arguments: Vec<Value>) -> Result<Value> {
+    let chars = argument(&arguments, 0)?.as_list()?;
     let str: Option<Vec<_>> = chars
-        .into_iter()
+        .iter()
         .map(|v| match v {
             Value::Str(s) => {
                 let chars: Vec<_> = s.chars().collect();
@@ -169,28 +211,111 @@ fn str_from_chars(arguments: Vec<Value>) -> super::Result<Value> {
         .collect();
     if let Some(str) = str {
         let str = String::from_iter(str);
-        Ok(Value::Str(str))
+        Ok(Value::Str(str.into()))
     } else {
         Ok(Value::Nil)
     }
 }
 
-fn read_file(arguments: Vec<Value>) -> super::Result<Value> {
-    let path = arguments.first().unwrap().as_str()?;
-    let Ok(contents) = std::fs::read_to_string(path) else {
+fn read_file(arguments: Vec<Value>) -> Result<Value> {
+    let path = argument(&arguments, 0)?.as_str()?;
+    let Ok(contents) = std::fs::read_to_string(&*path) else {
         return Ok(Value::Nil);
     };
-    Ok(Value::Str(contents))
+    Ok(Value::Str(contents.into()))
 }
 
 // To get around stack overflow w/ split by \n
-fn read_file_lines(arguments: Vec<Value>) -> super::Result<Value> {
+fn read_file_lines(arguments: Vec<Value>) -> Result<Value> {
     let Value::Str(contents) = read_file(arguments)? else {
         return Ok(Value::Nil);
     };
-    let lines = contents
-        .lines()
-        .map(|l| Value::Str(l.to_string()))
-        .collect();
+    let lines = contents.lines().map(|l| Value::Str(l.into())).collect();
     Ok(Value::List(lines))
 }
+
+/// `fn(item)` on each item.
+fn map(ctx: &mut Ctx, arguments: Vec<Value>) -> Result<Value> {
+    let list = argument(&arguments, 0)?.as_list()?;
+    let func = argument(&arguments, 1)?;
+    let items = list
+        .iter()
+        .map(|item| ctx.call(func, vec![item.clone()]))
+        .collect::<Result<_>>()?;
+    Ok(Value::List(items))
+}
+
+/// The items that `fn(item)` is truthy for.
+fn filter(ctx: &mut Ctx, arguments: Vec<Value>) -> Result<Value> {
+    let list = argument(&arguments, 0)?.as_list()?;
+    let func = argument(&arguments, 1)?;
+    let mut items = PVec::new();
+    for item in list.iter() {
+        if ctx.call(func, vec![item.clone()])?.is_truthy() {
+            items.push(item.clone());
+        }
+    }
+    Ok(Value::List(items))
+}
+
+/// `fn(acc, item)` on each item, starting with `initial_value`.
+fn fold(ctx: &mut Ctx, arguments: Vec<Value>) -> Result<Value> {
+    let list = argument(&arguments, 0)?.as_list()?;
+    let func = argument(&arguments, 1)?;
+    let initial_value = argument(&arguments, 2)?.clone();
+    let mut items = list.iter();
+    items.try_fold(initial_value, |acc, item| ctx.call(func, vec![acc, item.clone()]))
+}
+
+/// Sorted so that `fn(a, b)` is truthy if `a` should come before `b`. The
+/// sort is stable.
+fn sort_by(ctx: &mut Ctx, arguments: Vec<Value>) -> Result<Value> {
+    let list = argument(&arguments, 0)?.as_list()?;
+    let func = argument(&arguments, 1)?;
+    let items: Vec<_> = list.iter().cloned().collect();
+    let mut is_before = |a: &Value, b: &Value| -> Result<bool> {
+        Ok(ctx.call(func, vec![a.clone(), b.clone()])?.is_truthy())
+    };
+    let sorted = merge_sort(items, &mut is_before)?;
+    Ok(Value::List(sorted.into_iter().collect()))
+}
+
+/// A merge sort, since `is_before` can fail (which `sort_by` can't handle).
+fn merge_sort(
+    mut items: Vec<Value>,
+    is_before: &mut impl FnMut(&Value, &Value) -> Result<bool>,
+) -> Result<Vec<Value>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, is_before)?;
+    let right = merge_sort(right, is_before)?;
+
+    let mut sorted = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Only take from the right if it must come first, to keep it stable
+        let next = if is_before(r, l)? {
+            right.next()
+        } else {
+            left.next()
+        };
+        sorted.extend(next);
+    }
+    sorted.extend(left);
+    sorted.extend(right);
+    Ok(sorted)
+}
+
+/// The first item that `fn(item)` is truthy for, or `nil`.
+fn find(ctx: &mut Ctx, arguments: Vec<Value>) -> Result<Value> {
+    let list = argument(&arguments, 0)?.as_list()?;
+    let func = argument(&arguments, 1)?;
+    for item in list.iter() {
+        if ctx.call(func, vec![item.clone()])?.is_truthy() {
+            return Ok(item.clone());
+        }
+    }
+    Ok(Value::Nil)
+}
@@ -3,20 +3,61 @@ use crate::stream::Stream;
 pub fn lex(source: String) -> Vec<Token> {
     let mut tokens = vec![];
 
+    let source_len = source.len();
     let mut source = Stream::new(source.char_indices().collect());
-    while let Some((pos, char)) = source.next() {
+
+    // For each string interpolation currently being lexed, how many `{`s deep
+    // the code inside it is. When a `}` is found at depth 0, it ends the
+    // interpolation and the rest of the string is lexed.
+    let mut interpolations: Vec<usize> = vec![];
+
+    while let Some((start, char)) = source.next() {
         use TokenData::*;
+
+        // Set when a token is still produced, but something was wrong with
+        // it. The error gets its own token, just before the real one.
+        let mut error = None;
+
         let data = match char {
             '-' => Minus,
             '+' => Plus,
             '*' => Star,
+            '|' => Pipe,
             ';' => Semicolon,
-            '{' => OpenBrace,
-            '}' => CloseBrace,
+            '{' => {
+                if let Some(depth) = interpolations.last_mut() {
+                    *depth += 1;
+                }
+                OpenBrace
+            }
+            '}' => match interpolations.last_mut() {
+                Some(0) => {
+                    interpolations.pop();
+                    let (data, err) = source.scan_string(true);
+                    error = err;
+                    data
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    CloseBrace
+                }
+                None => CloseBrace,
+            },
             '(' => OpenParen,
             ')' => CloseParen,
+            '[' => OpenBracket,
+            ']' => CloseBracket,
             ',' => Comma,
+            ':' => Colon,
+            '.' => {
+                if source.next_if_char('.').is_some() {
+                    DotDot
+                } else {
+                    Dot
+                }
+            }
 
+            '=' if source.next_if_char('>').is_some() => FatArrow,
             '=' => source.equals_variant(Equals, EqualsEquals),
             '!' => source.equals_variant(Bang, BangEquals),
             '<' => source.equals_variant(Less, LessEquals),
@@ -24,44 +65,41 @@ pub fn lex(source: String) -> Vec<Token> {
 
             '/' => {
                 if source.next_if_char('/').is_some() {
+                    // `///` is a doc comment, but `////` is just a comment
+                    let is_doc = matches!(source.peek(), Some((_, '/')))
+                        && !matches!(source.peek_nth(1), Some((_, '/')));
                     // Comsume until end of line!
-                    source.advance_while(|(_, c)| *c != '\n');
-                    continue;
+                    let comment = source.next_while(|(_, c)| *c != '\n');
+                    if !is_doc {
+                        continue;
+                    }
+                    let text: String = comment.into_iter().skip(1).map(|(_, c)| c).collect();
+                    // Allow (but don't require) a space after the `///`
+                    let text = text.strip_prefix(' ').unwrap_or(&text);
+                    DocComment(text.to_string())
+                } else if source.next_if_char('*').is_some() {
+                    if source.skip_block_comment() {
+                        continue;
+                    }
+                    Error(TokenError::UnterminatedBlockComment)
                 } else {
                     Slash
                 }
             }
             '"' => {
-                let mut chars = vec![];
-                while let Some((_, char)) = source.next_if(|(_, c)| *c != '"') {
-                    chars.push(char)
-                }
-                let s = String::from_iter(chars);
-
-                if source.next_if_char('"').is_some() {
-                    Str(s)
-                } else {
-                    Error(TokenError::UnterminatedStringLiteral)
-                }
+                let (data, err) = source.scan_string(false);
+                error = err;
+                data
+            }
+            'r' if matches!(source.peek(), Some((_, '"' | '#'))) => {
+                let (data, err) = source.scan_raw_string();
+                error = err;
+                data
             }
             digit if digit.is_ascii_digit() => {
-                let mut digits = vec![digit];
-                digits.append(&mut source.scan_digits());
-
-                // Check for the digit before the `.` because it will
-                // short-circuit before the next_if side-effect happens.
-                if source
-                    .peek_many::<1>()
-                    .is_some_and(|(_, c)| c.is_ascii_digit())
-                    && source.next_if_char('.').is_some()
-                {
-                    digits.push('.');
-                    digits.append(&mut source.scan_digits());
-                }
-
-                let digits = String::from_iter(digits);
-                let num = digits.parse().expect("parsed string is valid f64");
-                Number(num)
+                let (data, err) = source.scan_number(digit);
+                error = err;
+                data
             }
             ident if is_alpha(ident) => {
                 let mut ident = vec![ident];
@@ -82,6 +120,10 @@ pub fn lex(source: String) -> Vec<Token> {
                     "true" => True,
                     "false" => False,
                     "nil" => Nil,
+                    "with" => With,
+                    "match" => Match,
+                    "type" => Type,
+                    "import" => Import,
                     _ => Identifier(ident),
                 }
             }
@@ -90,11 +132,20 @@ pub fn lex(source: String) -> Vec<Token> {
             unexpected_char => Error(TokenError::UnexpectedChar(unexpected_char)),
         };
 
-        let token = Token {
-            data,
-            pos: Pos(pos),
-        };
-        tokens.push(token);
+        if matches!(data, StrHead(_) | StrMid(_)) {
+            interpolations.push(0);
+        }
+
+        // The token ends where the next char starts
+        let end = source.peek().map(|(i, _)| *i).unwrap_or(source_len);
+        let span = Span { start, end };
+        if let Some(error) = error {
+            tokens.push(Token {
+                data: Error(error),
+                span,
+            });
+        }
+        tokens.push(Token { data, span });
     }
 
     tokens
@@ -111,11 +162,25 @@ fn is_alpha(ident: char) -> bool {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     pub data: TokenData,
-    pub pos: Pos,
+    pub span: Span,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub struct Pos(pub usize);
+/// A range of bytes in the source, `start..end`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// A span covering both `self` and `other`, and everything in between.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum TokenData {
@@ -144,18 +209,43 @@ pub enum TokenData {
     Nil,
     Number(f64),
     Str(String),
+    /// The start of an interpolated string, up to the first `{`.
+    StrHead(String),
+    /// The part of an interpolated string between a `}` and the next `{`.
+    StrMid(String),
+    /// The end of an interpolated string, from the last `}`.
+    StrTail(String),
     Identifier(String),
+    /// A `///` comment, without the `///`. Each line is its own token.
+    DocComment(String),
     OpenParen,
     CloseParen,
+    OpenBracket,
+    CloseBracket,
 
     Error(TokenError),
     Comma,
+    Colon,
+    Dot,
+    DotDot,
+    With,
+    Match,
+    FatArrow,
+    Type,
+    Pipe,
+    Import,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum TokenError {
     UnexpectedChar(char),
     UnterminatedStringLiteral,
+    InvalidEscape(char),
+    InvalidUnicodeEscape,
+    MalformedRawString,
+    UnterminatedBlockComment,
+    /// A number literal that couldn't be parsed, with its source text.
+    MalformedNumber(String),
 }
 
 impl<T: Clone> Stream<(T, char)> {
@@ -171,8 +261,207 @@ impl<T: Clone> Stream<(T, char)> {
         }
     }
 
+    /// Scans the rest of a string literal, after the opening `"`, or after
+    /// the `}` of an interpolation if `is_continuation`.
+    ///
+    /// The string is always returned, even if it has an error, so that the
+    /// rest of the literal doesn't get lexed as code.
+    fn scan_string(&mut self, is_continuation: bool) -> (TokenData, Option<TokenError>) {
+        let mut s = String::new();
+        let mut error = None;
+        let is_interpolated = loop {
+            match self.next().map(|(_, c)| c) {
+                None => {
+                    error = Some(TokenError::UnterminatedStringLiteral);
+                    break false;
+                }
+                Some('"') => break false,
+                Some('{') => break true,
+                Some('\\') => match self.scan_escape() {
+                    Ok(c) => s.push(c),
+                    Err(err) => {
+                        error.get_or_insert(err);
+                    }
+                },
+                Some(c) => s.push(c),
+            }
+        };
+
+        let data = match (is_continuation, is_interpolated) {
+            (false, false) => TokenData::Str(s),
+            (false, true) => TokenData::StrHead(s),
+            (true, true) => TokenData::StrMid(s),
+            (true, false) => TokenData::StrTail(s),
+        };
+        (data, error)
+    }
+
+    /// Scans an escape sequence, after the `\`.
+    fn scan_escape(&mut self) -> Result<char, TokenError> {
+        match self.next().map(|(_, c)| c) {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some('\\') => Ok('\\'),
+            Some('"') => Ok('"'),
+            Some('{') => Ok('{'),
+            Some('}') => Ok('}'),
+            Some('u') => self.scan_unicode_escape(),
+            Some(c) => Err(TokenError::InvalidEscape(c)),
+            None => Err(TokenError::UnterminatedStringLiteral),
+        }
+    }
+
+    /// Scans the `{XXXX}` part of a `\u{XXXX}` escape.
+    fn scan_unicode_escape(&mut self) -> Result<char, TokenError> {
+        if self.next_if_char('{').is_none() {
+            return Err(TokenError::InvalidUnicodeEscape);
+        }
+        let digits: String = self
+            .next_while(|(_, c)| c.is_ascii_hexdigit())
+            .into_iter()
+            .map(|(_, c)| c)
+            .collect();
+        if self.next_if_char('}').is_none() || digits.is_empty() || digits.len() > 6 {
+            return Err(TokenError::InvalidUnicodeEscape);
+        }
+
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or(TokenError::InvalidUnicodeEscape)
+    }
+
+    /// Scans the rest of a raw string literal, after the `r`.
+    ///
+    /// Raw strings have no escapes, and end at the first `"` followed by as
+    /// many `#`s as came before the opening `"`. e.g. `r#"a "quote""#`.
+    fn scan_raw_string(&mut self) -> (TokenData, Option<TokenError>) {
+        let hashes = self.advance_while(|(_, c)| *c == '#');
+        if self.next_if_char('"').is_none() {
+            let error = Some(TokenError::MalformedRawString);
+            return (TokenData::Str(String::new()), error);
+        }
+
+        let mut s = String::new();
+        while let Some((_, c)) = self.next() {
+            if c == '"' && self.next_hashes(hashes) {
+                return (TokenData::Str(s), None);
+            }
+            s.push(c);
+        }
+        (
+            TokenData::Str(s),
+            Some(TokenError::UnterminatedStringLiteral),
+        )
+    }
+
+    /// Consumes exactly `n` `#`s if they are next, without consuming anything
+    /// otherwise.
+    fn next_hashes(&mut self, n: usize) -> bool {
+        let all_hashes = (0..n).all(|i| matches!(self.peek_nth(i), Some((_, '#'))));
+        if all_hashes {
+            for _ in 0..n {
+                self.advance();
+            }
+        }
+        all_hashes
+    }
+
+    /// Skips the rest of a `/* */` comment, after the `/*`. Block comments
+    /// nest, so `/* a /* b */ c */` is one comment.
+    ///
+    /// Returns `false` if the comment is never closed.
+    fn skip_block_comment(&mut self) -> bool {
+        let mut depth = 1;
+        while let Some((_, c)) = self.next() {
+            match c {
+                '/' if self.next_if_char('*').is_some() => depth += 1,
+                '*' if self.next_if_char('/').is_some() => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Scans the rest of a number literal, after its first digit.
+    ///
+    /// Numbers may be decimal (`1.5`, `1.5e-3`) or integers in hex (`0xFF`),
+    /// octal (`0o17`) or binary (`0b1010`). Any of them may use `_` to
+    /// separate digits. Letters and digits directly after the number are
+    /// included in it, so that `0b102` or `12ab` are reported as a whole.
+    fn scan_number(&mut self, first: char) -> (TokenData, Option<TokenError>) {
+        let radix = match (first, self.peek().map(|(_, c)| *c)) {
+            ('0', Some('x' | 'X')) => 16,
+            ('0', Some('o' | 'O')) => 8,
+            ('0', Some('b' | 'B')) => 2,
+            _ => 10,
+        };
+
+        let mut text = String::from(first);
+        let num = if radix == 10 {
+            text.extend(self.scan_digits());
+
+            // Check for the digit before the `.` because it will
+            // short-circuit before the next_if side-effect happens.
+            if self
+                .peek_many::<1>()
+                .is_some_and(|(_, c)| c.is_ascii_digit())
+                && self.next_if_char('.').is_some()
+            {
+                text.push('.');
+                text.extend(self.scan_digits());
+            }
+
+            if let Some((_, e)) = self.next_if(|(_, c)| matches!(c, 'e' | 'E')) {
+                text.push(e);
+                if let Some((_, sign)) = self.next_if(|(_, c)| matches!(c, '+' | '-')) {
+                    text.push(sign);
+                }
+                text.extend(self.scan_digits());
+            }
+
+            text.extend(self.scan_trailing_alphanumerics());
+            text.replace('_', "").parse::<f64>().ok()
+        } else {
+            text.push(self.next().expect("radix prefix was peeked").1);
+            let digits: String = self.scan_trailing_alphanumerics().into_iter().collect();
+            text += &digits;
+            let digits = digits.replace('_', "");
+            // `from_str_radix` allows a leading sign, but a literal can't
+            // have one.
+            if digits.starts_with(['+', '-']) {
+                None
+            } else {
+                u64::from_str_radix(&digits, radix).ok().map(|n| n as f64)
+            }
+        };
+
+        match num {
+            Some(num) => (TokenData::Number(num), None),
+            None => (
+                TokenData::Number(0.0),
+                Some(TokenError::MalformedNumber(text)),
+            ),
+        }
+    }
+
+    /// Digits, with any `_` separators left in.
     fn scan_digits(&mut self) -> Vec<char> {
-        self.next_while(|(_, c)| c.is_ascii_digit())
+        self.next_while(|(_, c)| c.is_ascii_digit() || *c == '_')
+            .into_iter()
+            .map(|(_, c)| c)
+            .collect()
+    }
+
+    fn scan_trailing_alphanumerics(&mut self) -> Vec<char> {
+        self.next_while(|(_, c)| is_alphanumeric(*c))
             .into_iter()
             .map(|(_, c)| c)
             .collect()
@@ -0,0 +1,14 @@
+pub mod ast;
+mod diagnostic;
+mod engine;
+pub mod interperter;
+pub mod lexer;
+pub mod parser;
+mod stream;
+pub mod type_checker;
+pub mod util;
+pub mod vm;
+pub mod wasm_backend;
+
+pub use engine::{Engine, Error, FromValue, IntoValue, Result};
+pub use interperter::{Arity, Ctx, NativeFunc, Value};
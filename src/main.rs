@@ -1,18 +1,8 @@
-use std::{
-    fs,
-    io::{self, Write},
-    path::Path,
-};
+mod repl;
 
-use wasm_backend::gen_wasm;
+use std::{fs, io::Write, path::Path};
 
-mod ast;
-mod interperter;
-mod lexer;
-mod parser;
-mod stream;
-mod util;
-mod wasm_backend;
+use qua_lang::{wasm_backend::gen_wasm, Engine};
 
 fn main() {
     let mut args = std::env::args();
@@ -27,14 +17,17 @@ fn main() {
                 return;
             };
 
-            let ast = match ast_from_file(path.clone()) {
+            let source = fs::read_to_string(&path).expect("source file is readable");
+            let mut engine = new_engine(Engine::new());
+            engine.set_path(Path::new(&path));
+            let ast = match engine.check(&source) {
                 Ok(ast) => ast,
-                Err(err) => return eprintln!("Error parsing AST: {err:#?}"),
+                Err(err) => return eprintln!("{err}"),
             };
             let wasm = gen_wasm(ast);
 
             let path = Path::new(&path);
-            let Some(std::path::Component::Normal(filename)) = path.components().last() else {
+            let Some(std::path::Component::Normal(filename)) = path.components().next_back() else {
                 eprintln!("Error: path should contain filename");
                 return;
             };
@@ -53,86 +46,35 @@ fn main() {
                 Err(err) => eprintln!("Error writing to file: {err}"),
             }
         }
-        Some(path) => {
-            let source = fs::read_to_string(path).expect("source file is readable");
-            let mut env = (parser::Env::new(), interperter::Env::new());
-            run(source, &mut env);
-        }
-        None => {
-            run_repl();
-        }
+        Some(vm_flag) if vm_flag == "--vm" => match args.next() {
+            Some(path) => run_file(&path, Engine::with_vm()),
+            None => run_repl(Engine::with_vm()),
+        },
+        Some(path) => run_file(&path, Engine::new()),
+        None => run_repl(Engine::new()),
     }
 }
 
-fn run_repl() {
-    // start repl
-    eprintln!("qua v0.1.0");
-    let mut env = (parser::Env::new(), interperter::Env::new());
-    eprint!("> ");
-    while let Some(Ok(line)) = io::stdin().lines().next() {
-        run(line, &mut env);
-        eprint!("> ");
-    }
-    eprintln!("Goodbye! o/");
+/// Displays warnings as they are found.
+fn new_engine(mut engine: Engine) -> Engine {
+    engine.on_warning(|warning| eprint!("{warning}"));
+    engine
 }
 
-fn ast_from_file(path: String) -> parser::Parse<ast::Program> {
+fn run_file(path: &str, engine: Engine) {
     let source = fs::read_to_string(path).expect("source file is readable");
-    let mut env = (parser::Env::new(), interperter::Env::new());
-
-    let tokens = lexer::lex(source.clone());
-    parser::parse(tokens, &mut env.0)
+    let mut engine = new_engine(engine);
+    engine.set_path(Path::new(path));
+    run(&source, &mut engine);
 }
 
-fn run(source: String, env: &mut (parser::Env, interperter::Env)) {
-    let tokens = lexer::lex(source.clone());
-    // println!("{tokens:#?}");
-
-    let ast = parser::parse(tokens, &mut env.0);
-    // dbg!(&ast);
-
-    match ast {
-        Ok(ast) => {
-            let res = interperter::interpert(ast, &mut env.1);
-            if let Err(err) = res {
-                let msg = display_error(err, source);
-                eprintln!("{msg}");
-            }
-        }
-        Err(err) => println!("Error while parsing AST: {err:#?}"),
-    }
+fn run_repl(engine: Engine) {
+    repl::run(new_engine(engine));
 }
 
-fn display_error(err: interperter::Error, source: String) -> String {
-    format!(
-        "Error{}: {:#?}",
-        if let Some(pos) = err.pos {
-            let (line, col) = pos.calculate_line_col(&source);
-            format!(" at {line}:{col}")
-        } else {
-            "".to_string()
-        },
-        err.kind
-    )
-}
-
-impl lexer::Pos {
-    /// (line, col)
-    ///
-    /// e.g.
-    /// abc
-    /// def
-    /// f -> (2, 3)
-    fn calculate_line_col(&self, source: &str) -> (usize, usize) {
-        let mut line = 1;
-        let mut col = 0;
-        for c in source.chars().take(self.0) {
-            col += 1;
-            if c == '\n' {
-                line += 1;
-                col = 0;
-            }
-        }
-        (line, col)
+/// Runs the source, displaying any errors.
+fn run(source: &str, engine: &mut Engine) {
+    if let Err(err) = engine.eval(source) {
+        eprintln!("{err}");
     }
 }
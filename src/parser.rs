@@ -1,67 +1,131 @@
 mod env;
 mod tce;
 
+use std::rc::Rc;
+
 pub use env::Env;
 use tce::mark_tail_calls;
 
 use crate::{
     ast::{
-        BinaryExpr, BinaryOp, Binding, BindingMetadata, Block, Call, ElseBlock, Expr, Identifier,
-        IfExpr, Program, Stmt, UnaryExpr, UnaryOp,
+        Annotation, BinaryExpr, BinaryOp, Binding, BindingMetadata, Block, Call, ElseBlock, Expr,
+        FieldAccess, FieldPattern, Identifier, IfExpr, Import, Index, Interpolated, List,
+        ListPattern, Literal, LiteralValue, Match, MatchArm, Module, Pattern, Program, Record,
+        RecordField, RecordPattern, Stmt, TypeDecl, UnaryExpr, UnaryOp, VariantDecl, VariantInfo,
+        VariantPattern,
     },
-    lexer::{Pos, Token, TokenData},
+    interperter::Arity,
+    lexer::{Span, Token, TokenData, TokenError},
     stream::Stream,
 };
 
 /// A ancestor of `parent_scope` must include the stdlib.
-pub fn parse(tokens: Vec<Token>, env: &mut Env) -> Parse<Program> {
-    let mut program = Parser::new(tokens).parse_program(env)?;
+///
+/// Parsing doesn't stop at the first error. Every error found is returned
+/// (sorted by position), along with the program made of the statements that
+/// *did* parse. If there are any errors, the partial program is only useful
+/// for inspection, and should not be run. Warnings are returned either way.
+pub fn parse(tokens: Vec<Token>, env: &mut Env) -> (Program, Vec<Error>, Vec<Warning>) {
+    let mut parser = Parser::new(tokens);
+    let mut program = parser.parse_program(env);
     mark_tail_calls(&mut program);
-    Ok(program)
+
+    let mut errors = parser.errors;
+    errors.sort_by_key(|err| err.span.map(|s| s.start).unwrap_or(usize::MAX));
+    (program, errors, parser.warnings)
 }
 
 pub type Parse<T> = Result<T, Error>;
 
 struct Parser {
     tokens: Stream<Token>,
+    errors: Vec<Error>,
+    warnings: Vec<Warning>,
 }
 
 impl Parser {
     /// A ancestor of `parent_scope` must include the stdlib.
     fn new(tokens: Vec<Token>) -> Self {
+        // Report invalid tokens up front, and then parse as if they weren't
+        // there. Otherwise they show up as a confusing error about whatever
+        // the parser was expecting instead.
+        let mut errors = vec![];
+        let tokens = tokens
+            .into_iter()
+            .filter_map(|token| match token.data {
+                TokenData::Error(err) => {
+                    errors.push(Error {
+                        span: Some(token.span),
+                        kind: ErrorKind::InvalidToken(err),
+                    });
+                    None
+                }
+                _ => Some(token),
+            })
+            .collect();
+
         Parser {
             tokens: Stream::new(tokens),
+            errors,
+            warnings: vec![],
         }
     }
 }
 
 impl Parser {
-    fn parse_program(&mut self, env: &mut Env) -> Parse<Program> {
+    fn parse_program(&mut self, env: &mut Env) -> Program {
         let mut stmts = vec![];
-        while self.tokens.peek().is_some() {
-            let stmt = self.parse_stmt(env)?;
-            stmts.push(stmt);
+        loop {
+            self.skip_trailing_doc_comment();
+            if self.tokens.peek().is_none() {
+                break;
+            }
+            match self.parse_stmt(env) {
+                Ok(stmt) => stmts.push(stmt),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                    // A `}` at the top level doesn't close anything, so skip
+                    // it to make sure the loop progresses.
+                    self.matches(&TokenData::CloseBrace);
+                }
+            }
         }
-        Ok(stmts)
+        stmts
     }
 
     fn parse_stmt(&mut self, env: &mut Env) -> Parse<Stmt> {
         match self.parse_stmt_or_expr(env)? {
             StmtOrExpr::Stmt(stmt) => Ok(stmt),
-            StmtOrExpr::Expr(_) => Err(Error {
-                pos: self.tokens.peek().map(|t| t.pos),
-                kind: ErrorKind::ExpectedStmt,
-            }),
+            StmtOrExpr::Expr(_) => Err(self.error_at_next(ErrorKind::ExpectedStmt)),
         }
     }
 
     // This is done weirdly to allow for blocks to end in an expr easily
     fn parse_stmt_or_expr(&mut self, env: &mut Env) -> Parse<StmtOrExpr> {
+        let doc = self.parse_doc_comment();
         if self.matches(&TokenData::Let) {
-            let binding = self.parse_binding(env)?;
+            let mut binding = self.parse_binding(env)?;
+            binding.doc = doc.map(|(doc, _)| doc);
             self.expect(TokenData::Semicolon)?;
             Ok(StmtOrExpr::Stmt(Stmt::Let(binding)))
         } else {
+            if let Some((_, span)) = doc {
+                self.warnings.push(Warning {
+                    span,
+                    kind: WarningKind::DanglingDocComment,
+                });
+            }
+            if self.matches(&TokenData::Type) {
+                let type_decl = self.parse_type_decl(env)?;
+                self.expect(TokenData::Semicolon)?;
+                return Ok(StmtOrExpr::Stmt(Stmt::Type(type_decl)));
+            }
+            if self.matches(&TokenData::Import) {
+                let import = self.parse_import(env)?;
+                self.expect(TokenData::Semicolon)?;
+                return Ok(StmtOrExpr::Stmt(Stmt::Import(import)));
+            }
             let expr = self.parse_expr(env)?;
             if self.expect(TokenData::Semicolon).is_ok() {
                 Ok(StmtOrExpr::Stmt(Stmt::Expr(expr)))
@@ -71,40 +135,243 @@ impl Parser {
         }
     }
 
-    fn parse_binding(&mut self, env: &mut Env) -> Parse<Binding> {
-        let ident = self.parse_identifier(env)?;
+    fn parse_type_decl(&mut self, env: &mut Env) -> Parse<TypeDecl> {
+        let name = self.parse_identifier(env)?.name;
+        self.expect(TokenData::Equals)?;
+
+        let mut variants = vec![];
+        loop {
+            let ident = self.parse_identifier(env)?;
+            let arity = if self.matches(&TokenData::OpenParen) {
+                let fields =
+                    self.parse_arguments(Self::parse_identifier, TokenData::CloseParen, env)?;
+                Some(fields.len())
+            } else {
+                None
+            };
+            variants.push((ident, arity));
 
-        let is_func = self.matches(&TokenData::OpenParen);
+            if !self.matches(&TokenData::Pipe) {
+                break;
+            }
+        }
 
-        if is_func {
-            self.parse_func_binding(env, ident)
-        } else {
-            self.expect(TokenData::Equals)?;
+        // Every variant needs to know about the others, so they can only be
+        // declared once they have all been parsed.
+        let siblings: Vec<_> = variants
+            .iter()
+            .map(|(ident, arity)| (ident.name.clone(), *arity))
+            .collect();
+        let variants = variants
+            .into_iter()
+            .map(|(mut ident, arity)| {
+                let info = VariantInfo {
+                    type_name: name.clone(),
+                    name: ident.name.clone(),
+                    arity,
+                    siblings: siblings.clone(),
+                };
+                env.declare_variant(info.clone());
+                ident.location = Some(
+                    env.resolve(&ident.name)
+                        .expect("just declared variant in env"),
+                );
+                VariantDecl { ident, info }
+            })
+            .collect();
+
+        Ok(TypeDecl { variants })
+    }
 
-            // Insert the var *after* parsing value so that shadowing works
-            let name = ident.name.clone();
-            let value = self.parse_expr(env)?;
+    fn parse_import(&mut self, env: &mut Env) -> Parse<Import> {
+        let start = self.prev_span();
 
-            // Declare and resolve var
-            env.declare_local(name.clone());
-            let loc = env.resolve(&name).expect("just declared ident in env");
-            let ident = ident.resolve(loc);
+        if self.matches(&TokenData::OpenBrace) {
+            let names = self.parse_arguments(Self::parse_identifier, TokenData::CloseBrace, env)?;
+            self.expect_contextual("from")?;
+            let (path_span, path) = self.parse_module_path()?;
+            let module = self.load_module(env, path_span, &path);
+
+            // Declare the names even if the module couldn't be loaded, so
+            // that later uses of them don't cause spurious errors.
+            let mut fields = vec![];
+            for mut ident in names {
+                if let Ok(module) = &module {
+                    if !module.exports.fields.iter().any(|f| f.name == ident.name) {
+                        self.errors.push(Error {
+                            span: Some(ident.span),
+                            kind: ErrorKind::NotExported {
+                                name: ident.name.clone(),
+                                path: path.clone(),
+                            },
+                        });
+                    }
+                }
+                let variant = module
+                    .as_ref()
+                    .ok()
+                    .and_then(|module| module.variants.iter().find(|v| v.name == ident.name));
+                match variant {
+                    Some(info) => env.declare_variant(info.clone()),
+                    None => env.declare_local(ident.name.clone()),
+                }
+                ident.location = Some(
+                    env.resolve(&ident.name)
+                        .expect("just declared ident in env"),
+                );
+                fields.push(FieldPattern {
+                    name: ident.name.clone(),
+                    span: ident.span,
+                    pattern: Pattern::Identifier(ident),
+                });
+            }
+
+            let span = self.span_from(start);
+            Ok(Import {
+                module: module?,
+                pattern: Pattern::Record(RecordPattern { fields, span }),
+                span,
+            })
+        } else {
+            let (path_span, path) = self.parse_module_path()?;
+            self.expect_contextual("as")?;
+            let mut pattern = Pattern::Identifier(self.parse_identifier(env)?);
+            let module = self.load_module(env, path_span, &path);
 
-            Ok(Binding {
-                ident,
-                metadata: BindingMetadata::Var,
-                value,
+            // Declare the alias even if the module couldn't be loaded, so
+            // that later uses of it don't cause spurious errors.
+            declare_pattern(env, &mut pattern);
+            Ok(Import {
+                module: module?,
+                pattern,
+                span: self.span_from(start),
             })
         }
     }
 
+    /// The path of an import, and its span.
+    fn parse_module_path(&mut self) -> Parse<(Span, String)> {
+        self.consume_map(
+            |t| match &t.data {
+                TokenData::Str(path) => Some((t.span, path.clone())),
+                _ => None,
+            },
+            ErrorKind::ExpectedModulePath,
+        )
+    }
+
+    /// The module at the path of an import.
+    fn load_module(&mut self, env: &mut Env, span: Span, path: &str) -> Parse<Rc<Module>> {
+        let (module, warnings) = env.load_module(path).map_err(|kind| Error {
+            span: Some(span),
+            kind,
+        })?;
+        if !warnings.is_empty() {
+            self.warnings.push(Warning {
+                span,
+                kind: WarningKind::InModule {
+                    path: path.to_string(),
+                    source: module.source.clone(),
+                    warnings,
+                },
+            });
+        }
+        Ok(module)
+    }
+
+    /// Consecutive `///` lines, joined with newlines, and their span.
+    fn parse_doc_comment(&mut self) -> Option<(String, Span)> {
+        let mut doc: Option<(String, Span)> = None;
+        while let Some(token) = self
+            .tokens
+            .next_if(|t| matches!(t.data, TokenData::DocComment(_)))
+        {
+            let TokenData::DocComment(line) = token.data else {
+                unreachable!("just matched a doc comment");
+            };
+            doc = Some(match doc {
+                Some((text, span)) => (text + "\n" + &line, span.to(token.span)),
+                None => (line, token.span),
+            });
+        }
+        doc
+    }
+
+    /// Skips doc comments at the end of the program or a block, warning
+    /// about them. There isn't a statement after them to parse.
+    fn skip_trailing_doc_comment(&mut self) {
+        let mut i = 0;
+        while matches!(
+            self.tokens.peek_nth(i).map(|t| &t.data),
+            Some(TokenData::DocComment(_))
+        ) {
+            i += 1;
+        }
+        let at_end = matches!(
+            self.tokens.peek_nth(i).map(|t| &t.data),
+            None | Some(TokenData::CloseBrace)
+        );
+        if i > 0 && at_end {
+            if let Some((_, span)) = self.parse_doc_comment() {
+                self.warnings.push(Warning {
+                    span,
+                    kind: WarningKind::DanglingDocComment,
+                });
+            }
+        }
+    }
+
+    fn parse_binding(&mut self, env: &mut Env) -> Parse<Binding> {
+        // `let Variant(x) = ...` is a pattern, not a function
+        let is_variant = matches!(
+            self.tokens.peek().map(|t| &t.data),
+            Some(TokenData::Identifier(name)) if env.resolve_variant(name).is_some()
+        );
+        let is_func = !is_variant
+            && matches!(
+                self.tokens.peek_nth(1).map(|t| &t.data),
+                Some(TokenData::OpenParen)
+            );
+        if is_func {
+            let ident = self.parse_identifier(env)?;
+            self.expect(TokenData::OpenParen)?;
+            return self.parse_func_binding(env, ident);
+        }
+
+        let mut pattern = self.parse_pattern(env)?;
+        let start = pattern.span();
+        let annotation = self.parse_annotation()?;
+        self.expect(TokenData::Equals)?;
+
+        // Insert the vars *after* parsing value so that shadowing works
+        let value = self.parse_expr(env);
+
+        // Declare and resolve vars. Do this even if the value is invalid,
+        // so that later uses of them don't cause spurious errors.
+        declare_pattern(env, &mut pattern);
+        let value = value?;
+
+        Ok(Binding {
+            pattern,
+            metadata: BindingMetadata::Var,
+            value: Rc::new(value),
+            annotation,
+            doc: None,
+            span: self.span_from(start),
+        })
+    }
+
     fn parse_anon_closure(&mut self, env: &mut Env) -> Parse<Expr> {
         let mut env = env.create_scope();
 
+        // The `(` has already been consumed
+        let start = self.prev_span();
+
         // TODO: better name, maybe based on pos?
-        let mut func_indent = Identifier::new("self".to_string());
+        let mut func_indent = Identifier::new("self".to_string(), start);
 
         let func = self.parse_func_binding(&mut env, func_indent.clone())?;
+        let span = func.span;
         let func = Stmt::Let(func);
 
         func_indent.location = Some(env.resolve(&func_indent.name).unwrap());
@@ -113,39 +380,89 @@ impl Parser {
         Ok(Expr::Block(Block {
             stmts: vec![func],
             return_expr: Some(func_ref),
+            span,
         }))
     }
 
     fn parse_func_binding(&mut self, env: &mut Env, ident: Identifier) -> Parse<Binding> {
+        let start = ident.span;
         let name = ident.name.clone();
         env.declare_local(name.clone());
         let ident = ident.resolve(env.resolve(&name).expect("just declared ident"));
         let mut env = env.new_frame(name.clone());
 
+        // Each argument gets its own slot, named or not. Names bound by
+        // destructuring arguments come after all of them.
         let arguments = self.parse_arguments(
-            |parser, env| -> Parse<Identifier> {
-                let ident = parser.parse_identifier(env)?;
-                env.declare_local(ident.name.clone());
-                Ok(ident)
+            |parser, env| -> Parse<(Pattern, Option<Annotation>)> {
+                let pattern = parser.parse_pattern(env)?;
+                let annotation = parser.parse_annotation()?;
+                let name = match &pattern {
+                    Pattern::Identifier(ident) => ident.name.clone(),
+                    // Can't be referenced, since it isn't a valid identifier
+                    _ => "<argument>".to_string(),
+                };
+                env.declare_local(name);
+                Ok((pattern, annotation))
             },
+            TokenData::CloseParen,
             &mut env,
         )?;
+        let (mut arguments, annotations): (Vec<_>, Vec<_>) = arguments.into_iter().unzip();
+        env.set_arity(Arity::exact(arguments.len()));
+        for argument in &mut arguments {
+            if !matches!(argument, Pattern::Identifier(_)) {
+                declare_pattern(&mut env, argument);
+            }
+        }
 
+        let annotation = self.parse_annotation()?;
         self.expect(TokenData::Equals)?;
 
         let value = self.parse_expr(&mut env)?;
         let upvalues = env.upvalues();
 
         Ok(Binding {
-            ident,
+            pattern: Pattern::Identifier(ident),
             metadata: BindingMetadata::Func {
                 arguments,
+                annotations,
                 upvalues,
             },
-            value,
+            value: Rc::new(value),
+            annotation,
+            doc: None,
+            span: self.span_from(start),
         })
     }
 
+    /// An optional `: Type`.
+    fn parse_annotation(&mut self) -> Parse<Option<Annotation>> {
+        if !self.matches(&TokenData::Colon) {
+            return Ok(None);
+        }
+        let (name, span) = self.consume_map(
+            |t| match &t.data {
+                TokenData::Identifier(name) => Some((name.clone(), t.span)),
+                _ => None,
+            },
+            ErrorKind::ExpectedType,
+        )?;
+        let annotation = match name.as_str() {
+            "Num" => Annotation::Num,
+            "Str" => Annotation::Str,
+            "Bool" => Annotation::Bool,
+            "Nil" => Annotation::Nil,
+            _ => {
+                return Err(Error {
+                    span: Some(span),
+                    kind: ErrorKind::UnknownType { name },
+                })
+            }
+        };
+        Ok(Some(annotation))
+    }
+
     fn parse_expr(&mut self, env: &mut Env) -> Parse<Expr> {
         let binary_expr = self.parse_logic_or(env)?;
         Ok(binary_expr)
@@ -159,18 +476,10 @@ impl Parser {
     ) -> Parse<Expr> {
         let mut lhs = parse_operand(self, env)?;
 
-        while let Some((op, op_pos)) = self
-            .tokens
-            // preserve pos
-            .next_if_map(|t| parse_operator(t).map(|op| (op, t.pos)))
-        {
+        while let Some(op) = self.tokens.next_if_map(&parse_operator) {
             let rhs = parse_operand(self, env)?;
-            lhs = Expr::Binary(Box::new(BinaryExpr {
-                lhs,
-                op,
-                rhs,
-                op_pos,
-            }));
+            let span = lhs.span().to(rhs.span());
+            lhs = Expr::Binary(Box::new(BinaryExpr { lhs, op, rhs, span }));
         }
 
         Ok(lhs)
@@ -226,14 +535,15 @@ impl Parser {
 
     fn parse_unary(&mut self, env: &mut Env) -> Parse<Expr> {
         let op = self.tokens.next_if_map(|t| match t.data {
-            TokenData::Bang => Some(UnaryOp::Not),
-            TokenData::Minus => Some(UnaryOp::Negate),
+            TokenData::Bang => Some((UnaryOp::Not, t.span)),
+            TokenData::Minus => Some((UnaryOp::Negate, t.span)),
             _ => None,
         });
 
-        if let Some(op) = op {
+        if let Some((op, op_span)) = op {
             let rhs = self.parse_unary(env)?;
-            Ok(Expr::Unary(Box::new(UnaryExpr { op, rhs })))
+            let span = op_span.to(rhs.span());
+            Ok(Expr::Unary(Box::new(UnaryExpr { op, rhs, span })))
         } else {
             self.parse_call(env)
         }
@@ -242,28 +552,64 @@ impl Parser {
     fn parse_call(&mut self, env: &mut Env) -> Parse<Expr> {
         let mut target = self.parse_primary(env)?;
 
-        while let Some(token) = self
-            .tokens
-            .next_if(|t| matches!(t.data, TokenData::OpenParen))
-        {
-            let arguments = self.parse_arguments(Self::parse_expr, env)?;
-            target = Expr::Call(Call {
-                target: Box::new(target),
-                arguments,
-                is_tail_call: false,
-                pos: token.pos,
-            });
+        loop {
+            if self.matches(&TokenData::OpenParen) {
+                let arguments =
+                    self.parse_arguments(Self::parse_expr, TokenData::CloseParen, env)?;
+                let span = self.span_from(target.span());
+                if let Expr::Identifier(ident) = &target {
+                    if let Some(expected) = env.resolve_arity(&ident.name) {
+                        if !expected.accepts(arguments.len()) {
+                            self.errors.push(Error {
+                                span: Some(span),
+                                kind: ErrorKind::IncorrectArity {
+                                    name: ident.name.clone(),
+                                    expected,
+                                    given: arguments.len(),
+                                },
+                            });
+                        }
+                    }
+                }
+                target = Expr::Call(Call {
+                    target: Box::new(target),
+                    arguments,
+                    is_tail_call: false,
+                    span,
+                });
+            } else if self.matches(&TokenData::Dot) {
+                let field = self.parse_identifier(env)?;
+                let span = self.span_from(target.span());
+                target = Expr::Field(Box::new(FieldAccess {
+                    target,
+                    field: field.name,
+                    span,
+                }));
+            } else if self.matches(&TokenData::OpenBracket) {
+                let index = self.parse_expr(env)?;
+                self.expect(TokenData::CloseBracket)?;
+                let span = self.span_from(target.span());
+                target = Expr::Index(Box::new(Index {
+                    target,
+                    index,
+                    span,
+                }));
+            } else {
+                break;
+            }
         }
 
         Ok(target)
     }
 
+    /// Parses a comma separated list, up to and including `close`.
     fn parse_arguments<T>(
         &mut self,
         parse_arg: impl Fn(&mut Parser, &mut Env) -> Parse<T>,
+        close: TokenData,
         env: &mut Env,
     ) -> Parse<Vec<T>> {
-        if self.matches(&TokenData::CloseParen) {
+        if self.matches(&close) {
             return Ok(vec![]);
         }
 
@@ -273,7 +619,7 @@ impl Parser {
             let arg = parse_arg(self, env)?;
             arguments.push(arg);
 
-            if self.matches(&TokenData::CloseParen) {
+            if self.matches(&close) {
                 break;
             } else {
                 self.expect(TokenData::Comma)?;
@@ -283,70 +629,408 @@ impl Parser {
     }
 
     fn parse_primary(&mut self, env: &mut Env) -> Parse<Expr> {
-        use crate::ast::Literal::{Bool, Nil, Number, Str};
-        use Expr::Literal;
-        if self.matches(&TokenData::OpenBrace) {
+        use LiteralValue::{Bool, Nil, Number, Str};
+        if self.starts_record() && self.matches(&TokenData::OpenBrace) {
+            self.parse_record(env)
+        } else if self.matches(&TokenData::OpenBrace) {
             let block = self.parse_block(env)?;
             Ok(Expr::Block(block))
         } else if self.matches(&TokenData::If) {
             let if_expr = self.parse_if_expr(env)?;
             Ok(Expr::If(Box::new(if_expr)))
+        } else if self.matches(&TokenData::Match) {
+            let match_expr = self.parse_match(env)?;
+            Ok(Expr::Match(Box::new(match_expr)))
         } else if self.matches(&TokenData::OpenParen) {
             let closure = self.parse_anon_closure(env)?;
             Ok(closure)
+        } else if self.matches(&TokenData::OpenBracket) {
+            // The `[` has already been consumed
+            let start = self.prev_span();
+            let items = self.parse_arguments(Self::parse_expr, TokenData::CloseBracket, env)?;
+            Ok(Expr::List(List {
+                items,
+                span: self.span_from(start),
+            }))
+        } else if let Some((head, span)) = self.tokens.next_if_map(|t| match &t.data {
+            TokenData::StrHead(s) => Some((s.clone(), t.span)),
+            _ => None,
+        }) {
+            self.parse_interpolated(env, head, span)
         } else {
             self.consume_map(
                 |t| {
-                    Some(Ok(match &t.data {
-                        TokenData::True => Literal(Bool(true)),
-                        TokenData::False => Literal(Bool(false)),
-                        TokenData::Number(n) => Literal(Number(*n)),
-                        TokenData::Str(s) => Literal(Str(s.clone())),
-                        TokenData::Nil => Literal(Nil),
+                    let value = match &t.data {
+                        TokenData::True => Bool(true),
+                        TokenData::False => Bool(false),
+                        TokenData::Number(n) => Number(*n),
+                        TokenData::Str(s) => Str(s.clone()),
+                        TokenData::Nil => Nil,
                         TokenData::Identifier(name) => {
+                            let identifier = Identifier::new(name.clone(), t.span);
                             let location = match env.resolve(name) {
                                 Some(l) => l,
                                 None => {
                                     return Some(Err(Error {
-                                        pos: Some(t.pos),
-                                        kind: ErrorKind::VarNotInScope {
-                                            identifier: Identifier::new(name.clone()),
-                                        },
+                                        span: Some(t.span),
+                                        kind: ErrorKind::VarNotInScope { identifier },
                                     }))
                                 }
                             };
-                            let identifier = Identifier::new(name.clone()).resolve(location);
-                            return Some(Ok(Expr::Identifier(identifier)));
+                            return Some(Ok(Expr::Identifier(identifier.resolve(location))));
                         }
                         _ => return None,
-                    }))
+                    };
+                    Some(Ok(Expr::Literal(Literal {
+                        value,
+                        span: t.span,
+                    })))
                 },
                 ErrorKind::ExpectedPrimary,
             )?
         }
     }
 
+    fn parse_pattern(&mut self, env: &mut Env) -> Parse<Pattern> {
+        if self.matches(&TokenData::OpenBracket) {
+            self.parse_list_pattern(env)
+        } else if self.matches(&TokenData::OpenBrace) {
+            let start = self.prev_span();
+            let fields = self.parse_arguments(
+                |parser, env| -> Parse<FieldPattern> {
+                    let name = parser.parse_identifier(env)?;
+                    // `{ x }` is short for `{ x: x }`
+                    let pattern = if parser.matches(&TokenData::Colon) {
+                        parser.parse_pattern(env)?
+                    } else {
+                        Pattern::Identifier(name.clone())
+                    };
+                    Ok(FieldPattern {
+                        name: name.name,
+                        pattern,
+                        span: parser.span_from(name.span),
+                    })
+                },
+                TokenData::CloseBrace,
+                env,
+            )?;
+            for (i, field) in fields.iter().enumerate() {
+                if fields[..i].iter().any(|f| f.name == field.name) {
+                    self.errors.push(Error {
+                        span: Some(field.span),
+                        kind: ErrorKind::DuplicateField {
+                            name: field.name.clone(),
+                        },
+                    });
+                }
+            }
+            Ok(Pattern::Record(RecordPattern {
+                fields,
+                span: self.span_from(start),
+            }))
+        } else if self.matches(&TokenData::Minus) {
+            let start = self.prev_span();
+            let n = self.consume_map(
+                |t| match t.data {
+                    TokenData::Number(n) => Some(n),
+                    _ => None,
+                },
+                ErrorKind::ExpectedPattern,
+            )?;
+            Ok(Pattern::Literal(Literal {
+                value: LiteralValue::Number(-n),
+                span: self.span_from(start),
+            }))
+        } else if let Some(ident) = self.tokens.next_if_map(|t| match &t.data {
+            TokenData::Identifier(name) if name != "_" => {
+                Some(Identifier::new(name.clone(), t.span))
+            }
+            _ => None,
+        }) {
+            self.parse_variant_pattern(ident, env)
+        } else {
+            self.consume_map(
+                |t| {
+                    let value = match &t.data {
+                        TokenData::Identifier(name) if name == "_" => {
+                            return Some(Pattern::Wildcard(t.span))
+                        }
+                        TokenData::True => LiteralValue::Bool(true),
+                        TokenData::False => LiteralValue::Bool(false),
+                        TokenData::Number(n) => LiteralValue::Number(*n),
+                        TokenData::Str(s) => LiteralValue::Str(s.clone()),
+                        TokenData::Nil => LiteralValue::Nil,
+                        _ => return None,
+                    };
+                    Some(Pattern::Literal(Literal {
+                        value,
+                        span: t.span,
+                    }))
+                },
+                ErrorKind::ExpectedPattern,
+            )
+        }
+    }
+
+    /// Parses a pattern starting with a name, which is either a variant or
+    /// just the name to bind.
+    fn parse_variant_pattern(&mut self, ident: Identifier, env: &mut Env) -> Parse<Pattern> {
+        let fields = if self.matches(&TokenData::OpenParen) {
+            Some(self.parse_arguments(Self::parse_pattern, TokenData::CloseParen, env)?)
+        } else {
+            None
+        };
+
+        let Some(info) = env.resolve_variant(&ident.name).cloned() else {
+            if fields.is_none() {
+                return Ok(Pattern::Identifier(ident));
+            }
+            let span = self.span_from(ident.span);
+            self.errors.push(Error {
+                span: Some(ident.span),
+                kind: ErrorKind::NotAVariant { name: ident.name },
+            });
+            // Keep parsing as if it were a pattern that binds nothing
+            return Ok(Pattern::Wildcard(span));
+        };
+
+        let fields = fields.unwrap_or_default();
+        let expected = info.arity.unwrap_or(0);
+        if fields.len() != expected {
+            self.errors.push(Error {
+                span: Some(self.span_from(ident.span)),
+                kind: ErrorKind::VariantArity {
+                    name: ident.name,
+                    expected,
+                    given: fields.len(),
+                },
+            });
+        }
+
+        Ok(Pattern::Variant(Box::new(VariantPattern {
+            info,
+            fields,
+            span: self.span_from(ident.span),
+        })))
+    }
+
+    /// Parses the rest of a list pattern, after the `[`.
+    fn parse_list_pattern(&mut self, env: &mut Env) -> Parse<Pattern> {
+        let start = self.prev_span();
+        let mut items = vec![];
+        let mut rest = None;
+        while !self.matches(&TokenData::CloseBracket) {
+            if self.matches(&TokenData::DotDot) {
+                let dots = self.prev_span();
+                // `..` on its own ignores the rest
+                let pattern = match self.tokens.peek().map(|t| &t.data) {
+                    Some(TokenData::CloseBracket) => Pattern::Wildcard(dots),
+                    _ => match self.parse_pattern(env)? {
+                        pattern @ (Pattern::Identifier(_) | Pattern::Wildcard(_)) => pattern,
+                        pattern => {
+                            return Err(Error {
+                                span: Some(pattern.span()),
+                                kind: ErrorKind::ExpectedIdentifier,
+                            })
+                        }
+                    },
+                };
+                rest = Some(Box::new(pattern));
+                // The rest must be last
+                self.expect(TokenData::CloseBracket)?;
+                break;
+            }
+
+            items.push(self.parse_pattern(env)?);
+            if !self.matches(&TokenData::Comma) {
+                self.expect(TokenData::CloseBracket)?;
+                break;
+            }
+        }
+
+        Ok(Pattern::List(ListPattern {
+            items,
+            rest,
+            span: self.span_from(start),
+        }))
+    }
+
+    /// Whether the next `{` starts a record rather than a block, i.e. it is
+    /// followed by `name:`, or by an expression and then `with`.
+    fn starts_record(&self) -> bool {
+        let nth = |n| self.tokens.peek_nth(n).map(|t: &Token| &t.data);
+        if nth(0) != Some(&TokenData::OpenBrace) {
+            return false;
+        }
+        if self.is_field_at(1) {
+            return true;
+        }
+
+        // Look for a `with` that isn't nested inside anything, before the
+        // end of the first statement.
+        let mut depth = 0;
+        for n in 1.. {
+            match nth(n) {
+                None => return false,
+                Some(TokenData::With) if depth == 0 => return true,
+                Some(TokenData::Semicolon) if depth == 0 => return false,
+                Some(
+                    TokenData::OpenBrace
+                    | TokenData::OpenParen
+                    | TokenData::OpenBracket
+                    | TokenData::StrHead(_),
+                ) => depth += 1,
+                Some(
+                    TokenData::CloseBrace
+                    | TokenData::CloseParen
+                    | TokenData::CloseBracket
+                    | TokenData::StrTail(_),
+                ) => {
+                    if depth == 0 {
+                        return false;
+                    }
+                    depth -= 1;
+                }
+                Some(_) => {}
+            }
+        }
+        unreachable!("loop only ends by returning")
+    }
+
+    /// Whether the `n`th next token starts a `name: value` record field.
+    fn is_field_at(&self, n: usize) -> bool {
+        let nth = |n| self.tokens.peek_nth(n).map(|t: &Token| &t.data);
+        matches!(nth(n), Some(TokenData::Identifier(_))) && nth(n + 1) == Some(&TokenData::Colon)
+    }
+
+    /// Parses the rest of a record, after the `{`.
+    fn parse_record(&mut self, env: &mut Env) -> Parse<Expr> {
+        let start = self.prev_span();
+
+        let base = if self.is_field_at(0) {
+            None
+        } else {
+            let base = self.parse_expr(env)?;
+            self.expect(TokenData::With)?;
+            Some(Box::new(base))
+        };
+
+        let fields = self.parse_arguments(Self::parse_record_field, TokenData::CloseBrace, env)?;
+        for (i, field) in fields.iter().enumerate() {
+            if fields[..i].iter().any(|f| f.name == field.name) {
+                self.errors.push(Error {
+                    span: Some(field.span),
+                    kind: ErrorKind::DuplicateField {
+                        name: field.name.clone(),
+                    },
+                });
+            }
+        }
+
+        Ok(Expr::Record(Record {
+            base,
+            fields,
+            span: self.span_from(start),
+        }))
+    }
+
+    fn parse_record_field(&mut self, env: &mut Env) -> Parse<RecordField> {
+        let name = self.parse_identifier(env)?;
+        self.expect(TokenData::Colon)?;
+        let value = self.parse_expr(env)?;
+        Ok(RecordField {
+            name: name.name,
+            span: self.span_from(name.span),
+            value,
+        })
+    }
+
+    /// Parses the rest of an interpolated string, after the `StrHead`.
+    fn parse_interpolated(&mut self, env: &mut Env, head: String, start: Span) -> Parse<Expr> {
+        let str_part = |s, span| {
+            Expr::Literal(Literal {
+                value: LiteralValue::Str(s),
+                span,
+            })
+        };
+
+        let mut parts = vec![str_part(head, start)];
+        loop {
+            parts.push(self.parse_expr(env)?);
+
+            // The `}` ending the interpolation is part of the next token
+            let (s, span, is_end) = self.consume_map(
+                |t| match &t.data {
+                    TokenData::StrMid(s) => Some((s.clone(), t.span, false)),
+                    TokenData::StrTail(s) => Some((s.clone(), t.span, true)),
+                    _ => None,
+                },
+                ErrorKind::ExpectedToken(TokenData::CloseBrace),
+            )?;
+            parts.push(str_part(s, span));
+
+            if is_end {
+                break;
+            }
+        }
+
+        // Don't bother converting empty strings
+        parts.retain(|part| {
+            !matches!(part, Expr::Literal(Literal { value: LiteralValue::Str(s), .. }) if s.is_empty())
+        });
+
+        Ok(Expr::Interpolated(Interpolated {
+            parts,
+            span: self.span_from(start),
+        }))
+    }
+
     fn parse_block(&mut self, env: &mut Env) -> Parse<Block> {
         let mut env = env.create_scope();
 
+        // The `{` has already been consumed
+        let start = self.prev_span();
+
         let mut stmts = vec![];
         let mut return_expr = None;
-        while !self.matches(&TokenData::CloseBrace) {
-            let stmt_or_expr = self.parse_stmt_or_expr(&mut env)?;
-            match stmt_or_expr {
-                StmtOrExpr::Stmt(stmt) => stmts.push(stmt),
-                StmtOrExpr::Expr(e) => {
+        loop {
+            self.skip_trailing_doc_comment();
+            if self.matches(&TokenData::CloseBrace) {
+                break;
+            }
+            if self.tokens.peek().is_none() {
+                // Report the missing brace, but still keep what was parsed
+                let err = self.error_at_next(ErrorKind::ExpectedToken(TokenData::CloseBrace));
+                self.errors.push(err);
+                break;
+            }
+
+            match self.parse_stmt_or_expr(&mut env) {
+                Ok(StmtOrExpr::Stmt(stmt)) => stmts.push(stmt),
+                Ok(StmtOrExpr::Expr(e)) => {
                     return_expr = Some(Box::new(e));
+                    self.skip_trailing_doc_comment();
                     self.expect(TokenData::CloseBrace)?;
                     break;
                 }
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                }
             }
         }
 
-        Ok(Block { stmts, return_expr })
+        Ok(Block {
+            stmts,
+            return_expr,
+            span: self.span_from(start),
+        })
     }
 
     fn parse_if_expr(&mut self, env: &mut Env) -> Parse<IfExpr> {
+        // The `if` has already been consumed
+        let start = self.prev_span();
+
         let condition = self.parse_expr(env)?;
         self.expect(TokenData::OpenBrace)?;
         let then_block = self.parse_block(env)?;
@@ -366,18 +1050,62 @@ impl Parser {
             condition,
             then_block,
             else_block,
+            span: self.span_from(start),
+        })
+    }
+
+    fn parse_match(&mut self, env: &mut Env) -> Parse<Match> {
+        // The `match` has already been consumed
+        let start = self.prev_span();
+
+        let value = self.parse_expr(env)?;
+        self.expect(TokenData::OpenBrace)?;
+        let arms = self.parse_arguments(Self::parse_match_arm, TokenData::CloseBrace, env)?;
+
+        if let Some(missing) = missing_arm(&arms) {
+            self.warnings.push(Warning {
+                span: start.to(value.span()),
+                kind: WarningKind::NonExhaustiveMatch { missing },
+            });
+        }
+
+        Ok(Match {
+            value,
+            arms,
+            span: self.span_from(start),
+        })
+    }
+
+    fn parse_match_arm(&mut self, env: &mut Env) -> Parse<MatchArm> {
+        // The names bound by the pattern are only in scope for this arm
+        let mut env = env.create_scope();
+
+        let mut pattern = self.parse_pattern(&mut env)?;
+        declare_pattern(&mut env, &mut pattern);
+
+        let guard = if self.matches(&TokenData::If) {
+            Some(self.parse_expr(&mut env)?)
+        } else {
+            None
+        };
+        self.expect(TokenData::FatArrow)?;
+        let body = self.parse_expr(&mut env)?;
+
+        Ok(MatchArm {
+            pattern,
+            guard,
+            body,
         })
     }
 
     fn parse_identifier(&mut self, _env: &mut Env) -> Parse<Identifier> {
-        let name = self.consume_map(
+        self.consume_map(
             |t| match &t.data {
-                TokenData::Identifier(name) => Some(name.clone()),
+                TokenData::Identifier(name) => Some(Identifier::new(name.clone(), t.span)),
                 _ => None,
             },
             ErrorKind::ExpectedIdentifier,
-        )?;
-        Ok(Identifier::new(name))
+        )
     }
 }
 
@@ -387,20 +1115,147 @@ impl Parser {
     }
 
     fn consume_map<U>(&mut self, f: impl FnOnce(&Token) -> Option<U>, err: ErrorKind) -> Parse<U> {
-        self.tokens.next_if_map(f).ok_or_else(|| Error {
-            pos: self.tokens.peek().map(|t| t.pos),
-            kind: err,
-        })
+        self.tokens
+            .next_if_map(f)
+            .ok_or_else(|| self.error_at_next(err))
+    }
+
+    /// Skip tokens until a likely statement boundary: just after a `;`, or
+    /// just before a `let` or `}`.
+    fn synchronize(&mut self) {
+        while let Some(token) = self.tokens.peek() {
+            match token.data {
+                TokenData::Semicolon => {
+                    self.tokens.advance();
+                    return;
+                }
+                TokenData::Let | TokenData::CloseBrace => return,
+                _ => {
+                    self.tokens.advance();
+                }
+            }
+        }
+    }
+
+    /// Expect a word that is only a keyword in one place (like the `as` in
+    /// an import), so that it can still be used as a name everywhere else.
+    fn expect_contextual(&mut self, word: &str) -> Parse<()> {
+        let expected = TokenData::Identifier(word.to_string());
+        if self.matches(&expected) {
+            Ok(())
+        } else {
+            Err(self.error_at_next(ErrorKind::ExpectedToken(expected)))
+        }
     }
 
     fn expect(&mut self, expected_type: TokenData) -> Parse<()> {
         if self.matches(&expected_type) {
             Ok(())
         } else {
-            Err(Error {
-                pos: self.tokens.peek().map(|t| t.pos),
-                kind: ErrorKind::ExpectedToken(expected_type),
-            })
+            Err(self.error_at_next(ErrorKind::ExpectedToken(expected_type)))
+        }
+    }
+
+    /// Create an error pointing at the next token.
+    fn error_at_next(&self, kind: ErrorKind) -> Error {
+        Error {
+            span: self.tokens.peek().map(|t| t.span),
+            kind,
+        }
+    }
+
+    /// The span of the most recently consumed token.
+    fn prev_span(&self) -> Span {
+        self.tokens.prev().map(|t| t.span).unwrap_or_default()
+    }
+
+    /// A span from `start` up to and including the most recently consumed
+    /// token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.prev_span())
+    }
+}
+
+/// Checks a `match` on literals or variants for a catch-all arm, or an arm
+/// for every variant.
+///
+/// Returns `None` if the match is fine, or `Some` with the value known to be
+/// missing (if there is one). Matches with list or record patterns aren't
+/// checked, since they could well be exhaustive.
+fn missing_arm(arms: &[MatchArm]) -> Option<Option<String>> {
+    let is_catch_all =
+        |pattern: &Pattern| matches!(pattern, Pattern::Identifier(_) | Pattern::Wildcard(_));
+
+    let mut has_bool = [false; 2];
+    // The type's variants, and which of them are covered
+    let mut variants: Option<(&VariantInfo, Vec<&str>)> = None;
+    for arm in arms {
+        match &arm.pattern {
+            Pattern::List(_) | Pattern::Record(_) => return None,
+            // A guard could fail, so only unguarded arms count
+            _ if arm.guard.is_some() => {}
+            pattern if is_catch_all(pattern) => return None,
+            Pattern::Literal(Literal {
+                value: LiteralValue::Bool(b),
+                ..
+            }) => has_bool[*b as usize] = true,
+            Pattern::Variant(variant) => {
+                let (_, covered) = variants.get_or_insert_with(|| (&variant.info, Vec::new()));
+                // An arm only covers the whole variant if its fields can't fail
+                if variant.fields.iter().all(is_catch_all) {
+                    covered.push(&variant.info.name);
+                }
+            }
+            Pattern::Identifier(_) | Pattern::Wildcard(_) | Pattern::Literal(_) => {}
+        }
+    }
+
+    if let Some((info, covered)) = variants {
+        return info
+            .siblings
+            .iter()
+            .find(|(name, _)| !covered.contains(&name.as_str()))
+            .map(|(name, arity)| match arity {
+                Some(arity) => Some(format!("{name}({})", vec!["_"; *arity].join(", "))),
+                None => Some(name.clone()),
+            });
+    }
+    match has_bool {
+        [true, true] => None,
+        [true, false] => Some(Some("true".to_string())),
+        [false, true] => Some(Some("false".to_string())),
+        [false, false] => Some(None),
+    }
+}
+
+/// Declares every name bound by the pattern, in order, and resolves them.
+fn declare_pattern(env: &mut Env, pattern: &mut Pattern) {
+    match pattern {
+        Pattern::Identifier(ident) => {
+            env.declare_local(ident.name.clone());
+            let loc = env
+                .resolve(&ident.name)
+                .expect("just declared ident in env");
+            ident.location = Some(loc);
+        }
+        Pattern::Wildcard(_) | Pattern::Literal(_) => {}
+        Pattern::List(list) => {
+            for item in &mut list.items {
+                declare_pattern(env, item);
+            }
+            if let Some(rest) = &mut list.rest {
+                declare_pattern(env, rest);
+            }
+        }
+        Pattern::Record(record) => {
+            for field in &mut record.fields {
+                declare_pattern(env, &mut field.pattern);
+            }
+        }
+        Pattern::Variant(variant) => {
+            for field in &mut variant.fields {
+                declare_pattern(env, field);
+            }
         }
     }
 }
@@ -411,13 +1266,11 @@ enum StmtOrExpr {
     Expr(Expr),
 }
 
-#[expect(dead_code, reason = "Pretty error printing not implemented yet")]
 #[derive(Debug)]
 pub struct Error {
-    pos: Option<Pos>,
-    kind: ErrorKind,
+    pub span: Option<Span>,
+    pub kind: ErrorKind,
 }
-#[expect(dead_code, reason = "Pretty error printing not implemented yet")]
 #[allow(
     clippy::enum_variant_names,
     reason = "Not repeating the enum name, and adds important context."
@@ -427,7 +1280,78 @@ pub enum ErrorKind {
     ExpectedToken(TokenData),
     ExpectedIdentifier,
     ExpectedPrimary,
-    ExpectedUnary,
     ExpectedStmt,
-    VarNotInScope { identifier: Identifier },
+    VarNotInScope {
+        identifier: Identifier,
+    },
+    InvalidToken(TokenError),
+    DuplicateField {
+        name: String,
+    },
+    ExpectedPattern,
+    /// A pattern like `Name(...)` where `Name` isn't a variant.
+    NotAVariant {
+        name: String,
+    },
+    /// A variant pattern with the wrong number of fields.
+    VariantArity {
+        name: String,
+        expected: usize,
+        given: usize,
+    },
+    /// A call to a known function with the wrong number of arguments.
+    IncorrectArity {
+        name: String,
+        expected: Arity,
+        given: usize,
+    },
+    /// A `:` that isn't followed by a type.
+    ExpectedType,
+    UnknownType {
+        name: String,
+    },
+    /// An import of something that isn't a string literal.
+    ExpectedModulePath,
+    ModuleNotFound {
+        path: String,
+        reason: String,
+    },
+    /// A module that (indirectly) imports itself. `cycle` is the path of
+    /// each import, starting and ending with the same module.
+    ImportCycle {
+        cycle: Vec<String>,
+    },
+    /// A module that failed to parse.
+    InvalidModule {
+        path: String,
+        source: String,
+        errors: Vec<Error>,
+    },
+    NotExported {
+        name: String,
+        path: String,
+    },
+}
+
+/// Something that is allowed, but is probably a mistake. Unlike errors,
+/// warnings don't stop the program from running.
+#[derive(Debug)]
+pub struct Warning {
+    pub span: Span,
+    pub kind: WarningKind,
+}
+#[derive(Debug)]
+pub enum WarningKind {
+    /// A `match` on literals without a catch-all arm. `missing` is the value
+    /// not handled, if it is known.
+    NonExhaustiveMatch { missing: Option<String> },
+    /// A `///` comment that isn't followed by a `let` binding, so it doesn't
+    /// document anything.
+    DanglingDocComment,
+    /// Warnings from parsing an imported module.
+    InModule {
+        path: String,
+        source: String,
+        warnings: Vec<Warning>,
+    },
 }
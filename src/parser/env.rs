@@ -1,32 +1,202 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::HashMap,
+    fs,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
+use super::{ErrorKind, Warning};
 use crate::{
-    ast::{IdentLocation, StackIndex, Upvalue, UpvalueIndex},
+    ast::{
+        Expr, IdentLocation, Identifier, Module, Record, RecordField, StackIndex, Upvalue,
+        UpvalueIndex, VariantInfo,
+    },
+    interperter::Arity,
+    lexer::{self, Span},
     util::nonempty_vec::NEVec,
 };
 
 type Scope = Vec<Local>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Env {
     /// A list of call frames, which is a list of blocks, each with a list
     /// of locals.
     frames: NEVec<CallFrame>,
+    /// The file being parsed, which imports are relative to. Without one,
+    /// they are relative to the working directory.
+    path: Option<PathBuf>,
+    modules: Modules,
+    /// Functions the host registered, which modules can see too.
+    host: Vec<(String, Arity)>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Modules {
+    /// Every module parsed so far, by canonical path.
+    loaded: HashMap<PathBuf, Rc<Module>>,
+    /// The file given to `set_path`, which imports start from, with the path
+    /// as it was given.
+    root: Option<(PathBuf, String)>,
+    /// The modules currently being parsed (each importing the next), with
+    /// the path as written in the import.
+    loading: Vec<(PathBuf, String)>,
 }
 
 impl Env {
     pub fn new() -> Self {
+        Self::with_host(Vec::new())
+    }
+
+    /// An env where the host's functions are defined right after the stdlib.
+    fn with_host(host: Vec<(String, Arity)>) -> Self {
         let mut env = Env {
             frames: NEVec::default(),
+            path: None,
+            modules: Modules::default(),
+            host: Vec::new(),
         };
 
         crate::interperter::stub_stdlib(&mut env);
+        for (name, arity) in &host {
+            env.declare_func(name.clone(), *arity);
+        }
+        env.host = host;
         // new scope for non-stdlib
         env.frames.last_mut().scopes.push(Vec::default());
 
         env
     }
 
+    /// Sets the file being parsed, so that imports can be found relative to
+    /// it (and so that it can't be imported by its own imports). Returns the
+    /// file that was set before, for `restore_path`.
+    pub fn set_path(&mut self, path: &Path) -> Option<(PathBuf, String)> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.restore_path(Some((canonical, path.display().to_string())))
+    }
+
+    /// Puts back the file that `set_path` replaced (or none). Returns the
+    /// file that was set.
+    pub fn restore_path(
+        &mut self,
+        root: Option<(PathBuf, String)>,
+    ) -> Option<(PathBuf, String)> {
+        self.path = root.as_ref().map(|(canonical, _)| canonical.clone());
+        std::mem::replace(&mut self.modules.root, root)
+    }
+
+    /// Parses the module at `written` (relative to the current file), along
+    /// with any warnings it has. A module that was already parsed is reused,
+    /// without the warnings.
+    pub(super) fn load_module(
+        &mut self,
+        written: &str,
+    ) -> Result<(Rc<Module>, Vec<Warning>), ErrorKind> {
+        let dir = self
+            .path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new("."));
+        let not_found = |err: std::io::Error| ErrorKind::ModuleNotFound {
+            path: written.to_string(),
+            reason: err.to_string(),
+        };
+        let path = dir.join(written).canonicalize().map_err(not_found)?;
+
+        if let Some(module) = self.modules.loaded.get(&path) {
+            return Ok((module.clone(), Vec::new()));
+        }
+        let importers: Vec<_> = self
+            .modules
+            .root
+            .iter()
+            .chain(&self.modules.loading)
+            .collect();
+        if let Some(start) = importers.iter().position(|(p, _)| *p == path) {
+            let mut cycle: Vec<_> = importers[start..]
+                .iter()
+                .map(|(_, written)| written.clone())
+                .collect();
+            cycle.push(written.to_string());
+            return Err(ErrorKind::ImportCycle { cycle });
+        }
+
+        let source = fs::read_to_string(&path).map_err(not_found)?;
+
+        // Modules can only see the stdlib and the host's functions, not
+        // whatever is importing them
+        let mut module_env = Env::with_host(self.host.clone());
+        module_env.path = Some(path.clone());
+        module_env.modules = std::mem::take(&mut self.modules);
+        module_env
+            .modules
+            .loading
+            .push((path.clone(), written.to_string()));
+
+        let tokens = lexer::lex(source.clone());
+        let (program, errors, warnings) = super::parse(tokens, &mut module_env);
+        let (exports, variants) = module_env.exports();
+
+        self.modules = std::mem::take(&mut module_env.modules);
+        self.modules.loading.pop();
+
+        if !errors.is_empty() {
+            return Err(ErrorKind::InvalidModule {
+                path: written.to_string(),
+                source,
+                errors,
+            });
+        }
+
+        let module = Rc::new(Module {
+            path: path.clone(),
+            name: written.to_string(),
+            source,
+            program,
+            exports,
+            variants,
+        });
+        self.modules.loaded.insert(path, module.clone());
+        Ok((module, warnings))
+    }
+
+    /// A record of every top-level binding, and the variants among them.
+    fn exports(&mut self) -> (Record, Vec<VariantInfo>) {
+        let mut names: Vec<String> = Vec::new();
+        let top_level = self.frames[0].scopes.last().expect("scopes is nonempty");
+        for local in top_level {
+            if !names.contains(&local.name) {
+                names.push(local.name.clone());
+            }
+        }
+
+        let fields = names
+            .iter()
+            .map(|name| {
+                let mut ident = Identifier::new(name.clone(), Span::default());
+                ident.location = self.resolve(name);
+                RecordField {
+                    name: name.clone(),
+                    value: Expr::Identifier(ident),
+                    span: Span::default(),
+                }
+            })
+            .collect();
+        let variants = names
+            .iter()
+            .filter_map(|name| self.resolve_variant(name).cloned())
+            .collect();
+
+        let exports = Record {
+            base: None,
+            fields,
+            span: Span::default(),
+        };
+        (exports, variants)
+    }
+
     pub fn create_scope(&mut self) -> ScopeGuard<'_> {
         ScopeGuard::new(self)
     }
@@ -36,10 +206,84 @@ impl Env {
     }
 
     pub fn declare_local(&mut self, name: String) {
-        let local = Local { name };
+        let local = Local {
+            name,
+            variant: None,
+            arity: None,
+        };
         self.frames.last_mut().scopes.last_mut().push(local);
     }
 
+    /// Declares a function whose arity is known, so that calls to it can be
+    /// checked.
+    pub fn declare_func(&mut self, name: String, arity: Arity) {
+        let local = Local {
+            name,
+            variant: None,
+            arity: Some(arity),
+        };
+        self.frames.last_mut().scopes.last_mut().push(local);
+    }
+
+    /// Declares a function the host registered, which imported modules can
+    /// call too.
+    pub fn declare_host_func(&mut self, name: String, arity: Arity) {
+        self.declare_func(name.clone(), arity);
+        self.host.push((name, arity));
+    }
+
+    /// Declares the constructor (or value) of a variant, so that patterns
+    /// can refer to it.
+    pub fn declare_variant(&mut self, info: VariantInfo) {
+        let local = Local {
+            name: info.name.clone(),
+            arity: info.arity.map(Arity::exact),
+            variant: Some(info),
+        };
+        self.frames.last_mut().scopes.last_mut().push(local);
+    }
+
+    /// The variant that `name` refers to, if it isn't shadowed by a regular
+    /// local.
+    pub fn resolve_variant(&self, name: &str) -> Option<&VariantInfo> {
+        self.find_local(name)
+            .and_then(|local| local.variant.as_ref())
+    }
+
+    /// The arity of the function that `name` refers to, if it is known.
+    pub fn resolve_arity(&self, name: &str) -> Option<Arity> {
+        self.find_local(name).and_then(|local| local.arity)
+    }
+
+    /// The innermost local named `name`, in any frame.
+    fn find_local(&self, name: &str) -> Option<&Local> {
+        self.frames
+            .iter()
+            .rev()
+            .flat_map(|frame| {
+                frame
+                    .scopes
+                    .iter()
+                    .rev()
+                    .flat_map(|scope| scope.iter().rev())
+            })
+            .find(|local| local.name == name)
+    }
+
+    /// The names declared at the top level (outside the stdlib) that aren't
+    /// shadowed, in the order they were declared, with where they are.
+    pub fn globals(&self) -> Vec<(String, StackIndex)> {
+        let frame = &self.frames[0];
+        let stdlib_len = frame.scopes[0].len();
+        let locals: Vec<&Local> = frame.scopes.iter().skip(1).flatten().collect();
+        locals
+            .iter()
+            .enumerate()
+            .filter(|(i, local)| !locals[i + 1..].iter().any(|l| l.name == local.name))
+            .map(|(i, local)| (local.name.clone(), StackIndex(stdlib_len + i)))
+            .collect()
+    }
+
     // Look for the most deeply-scoped local with the given name.
     pub fn resolve(&mut self, name: &str) -> Option<IdentLocation> {
         // can always subtract 1 from len b/c it is non-empty (a NEVec)
@@ -101,7 +345,13 @@ impl Env {
     }
 }
 
-#[derive(Debug, Default)]
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct CallFrame {
     scopes: NEVec<Scope>,
     upvalues: Vec<Upvalue>,
@@ -146,6 +396,16 @@ impl<'a> FrameGuard<'a> {
         FrameGuard(env)
     }
 
+    /// Sets the arity of the function, both where it is bound and in slot 0
+    /// (for recursion).
+    pub fn set_arity(&mut self, arity: Arity) {
+        let frame = self.frames.len() - 1;
+        self.frames[frame].scopes[0][0].arity = Some(arity);
+        // The name was declared just before the frame was made
+        let outer = self.frames[frame - 1].scopes.last_mut();
+        outer.last_mut().expect("func name was declared").arity = Some(arity);
+    }
+
     // Take `self` so that upvalues must be up-to-date.
     pub fn upvalues(mut self) -> Vec<Upvalue> {
         let upvalues = self.frames.pop_unchecked().upvalues;
@@ -172,7 +432,11 @@ impl Drop for FrameGuard<'_> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Local {
     name: String,
+    /// Set if this is a variant's constructor.
+    variant: Option<VariantInfo>,
+    /// Set if this is a function whose arity is known.
+    arity: Option<Arity>,
 }
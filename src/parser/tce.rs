@@ -1,7 +1,6 @@
-use crate::ast::{
-    BinaryExpr, Binding, BindingMetadata, Block, Call, ElseBlock, Expr, IfExpr, Program, Stmt,
-    UnaryExpr,
-};
+use std::rc::Rc;
+
+use crate::ast::{Binding, BindingMetadata, Block, ElseBlock, Expr, IfExpr, Program, Stmt};
 
 pub fn mark_tail_calls(program: &mut Program) {
     mark_functions(program)
@@ -22,7 +21,7 @@ fn mark_binding(binding: &mut Binding) {
         return;
     }
 
-    mark_expr(&mut binding.value, true);
+    mark_expr(Rc::make_mut(&mut binding.value), true);
 }
 
 /// Parameters:
@@ -34,11 +33,43 @@ fn mark_expr(expr: &mut Expr, is_final: bool) {
         Expr::Block(block) => mark_block(block, is_final),
         Expr::Call(call) if is_final => call.is_tail_call = true,
         Expr::If(if_expr) => mark_if_expr(if_expr, is_final),
+        Expr::Match(match_expr) => {
+            mark_expr(&mut match_expr.value, false);
+            for arm in &mut match_expr.arms {
+                if let Some(guard) = &mut arm.guard {
+                    mark_expr(guard, false);
+                }
+                mark_expr(&mut arm.body, is_final);
+            }
+        }
         Expr::Binary(binary_expr) => {
             mark_expr(&mut binary_expr.lhs, false);
             mark_expr(&mut binary_expr.rhs, false);
         }
         Expr::Unary(unary_expr) => mark_expr(&mut unary_expr.rhs, false),
+        Expr::Interpolated(interpolated) => {
+            for part in &mut interpolated.parts {
+                mark_expr(part, false);
+            }
+        }
+        Expr::List(list) => {
+            for item in &mut list.items {
+                mark_expr(item, false);
+            }
+        }
+        Expr::Index(index) => {
+            mark_expr(&mut index.target, false);
+            mark_expr(&mut index.index, false);
+        }
+        Expr::Record(record) => {
+            if let Some(base) = &mut record.base {
+                mark_expr(base, false);
+            }
+            for field in &mut record.fields {
+                mark_expr(&mut field.value, false);
+            }
+        }
+        Expr::Field(field_access) => mark_expr(&mut field_access.target, false),
         Expr::Literal(_) | Expr::Identifier(_) | Expr::Call(_) => {}
     }
 }
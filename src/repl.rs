@@ -0,0 +1,189 @@
+use std::{
+    env, fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use qua_lang::{
+    lexer::{self, TokenData, TokenError},
+    Engine, Value,
+};
+
+const HELP: &str = "\
+:ast <code>     show the syntax tree of the code, without running it
+:tokens <code>  show the tokens of the code
+:env            show everything defined so far
+:load <file>    run a file, keeping what it defines
+:reset          forget everything defined so far
+:quit           exit (so does ctrl-d)
+:help           show this message";
+
+pub fn run(mut engine: Engine) {
+    // Only talk to a person, so that piped input gives just the output.
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        eprintln!("qua v0.1.0 (:help for commands)");
+    }
+
+    let mut history = History::open();
+    let mut lines = io::stdin().lines();
+    // The lines of an entry that isn't finished yet.
+    let mut buffer = String::new();
+    loop {
+        if interactive {
+            eprint!("{}", if buffer.is_empty() { "> " } else { "... " });
+        }
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            history.add(line.trim());
+            match command(line.trim(), &mut engine) {
+                Flow::Continue => continue,
+                Flow::Quit => break,
+            }
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if is_complete(&buffer) {
+            let entry = std::mem::take(&mut buffer);
+            history.add(entry.trim_end());
+            eval(&entry, &mut engine);
+        }
+    }
+    // Whatever is left is run anyway, to show why it's unfinished.
+    if !buffer.is_empty() {
+        eval(&buffer, &mut engine);
+    }
+
+    if interactive {
+        eprintln!("Goodbye! o/");
+    }
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+fn command(line: &str, engine: &mut Engine) -> Flow {
+    let (name, argument) = line.split_once(' ').unwrap_or((line, ""));
+    let argument = argument.trim();
+    match (name, argument) {
+        (":quit", _) => return Flow::Quit,
+        (":help", _) => println!("{HELP}"),
+        (":env", _) => {
+            for (name, value) in engine.globals() {
+                println!("{name} = {}", show(&value));
+            }
+        }
+        (":reset", _) => engine.reset(),
+        (":ast" | ":tokens" | ":load", "") => eprintln!("error: `{name}` needs an argument"),
+        (":ast", code) => match engine.parse(&with_semicolon(code)) {
+            Ok(ast) => println!("{ast:#?}"),
+            Err(err) => eprintln!("{err}"),
+        },
+        (":tokens", code) => {
+            for token in lexer::lex(code.to_string()) {
+                println!("{}..{} {:?}", token.span.start, token.span.end, token.data);
+            }
+        }
+        (":load", path) => match fs::read_to_string(path) {
+            Ok(source) => {
+                if let Err(err) = engine.eval_file(Path::new(path), &source) {
+                    eprintln!("{err}");
+                }
+            }
+            Err(err) => eprintln!("error: could not read `{path}`: {err}"),
+        },
+        _ => eprintln!("error: unknown command `{name}` (:help for commands)"),
+    }
+    Flow::Continue
+}
+
+/// Runs the entry, showing its value (if it has one) or its errors.
+fn eval(entry: &str, engine: &mut Engine) {
+    if entry.trim().is_empty() {
+        return;
+    }
+    match engine.eval(&with_semicolon(entry)) {
+        Ok(Value::Nil) => {}
+        Ok(value) => println!("{}", show(&value)),
+        Err(err) => eprintln!("{err}"),
+    }
+}
+
+/// Shows a value the way it would be written, so strings are quoted.
+fn show(value: &Value) -> String {
+    match value {
+        Value::Str(str) => format!("{str:?}"),
+        value => value.to_string(),
+    }
+}
+
+/// Whether the entry could be finished as is, i.e. it doesn't have any
+/// unclosed brackets, strings, or comments. If it can't ever be finished,
+/// it's complete too, so that the error is shown.
+fn is_complete(entry: &str) -> bool {
+    let mut depth: isize = 0;
+    for token in lexer::lex(entry.to_string()) {
+        match token.data {
+            TokenData::OpenBrace | TokenData::OpenParen | TokenData::OpenBracket => depth += 1,
+            TokenData::CloseBrace | TokenData::CloseParen | TokenData::CloseBracket => depth -= 1,
+            TokenData::Error(
+                TokenError::UnterminatedStringLiteral | TokenError::UnterminatedBlockComment,
+            ) => return false,
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+/// Ends the code with a `;` if it doesn't have one, so that a trailing
+/// expression is a statement (whose value is shown). Code that doesn't lex
+/// is left alone, since the `;` could end up in an unterminated string.
+fn with_semicolon(code: &str) -> String {
+    let tokens = lexer::lex(code.to_string());
+    if tokens
+        .iter()
+        .any(|token| matches!(token.data, TokenData::Error(_)))
+    {
+        return code.to_string();
+    }
+    let last = tokens
+        .into_iter()
+        .rfind(|token| !matches!(token.data, TokenData::DocComment(_)));
+    match last {
+        Some(token) if token.data != TokenData::Semicolon => format!("{code}\n;"),
+        _ => code.to_string(),
+    }
+}
+
+/// Every entry, appended to `$QUA_HISTORY` (or `~/.qua_history`). If it
+/// can't be opened, history isn't kept.
+struct History(Option<fs::File>);
+
+impl History {
+    fn open() -> Self {
+        let path = env::var_os("QUA_HISTORY")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".qua_history")));
+        let file = path.and_then(|path| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .ok()
+        });
+        History(file)
+    }
+
+    fn add(&mut self, entry: &str) {
+        if let Some(file) = &mut self.0 {
+            // Losing history isn't worth interrupting the session over.
+            let _ = writeln!(file, "{entry}");
+        }
+    }
+}
@@ -78,6 +78,11 @@ impl<T> Stream<T> {
         self.peek_many::<0>()
     }
 
+    /// The element that was most recently consumed.
+    pub fn prev(&self) -> Option<&T> {
+        self.data.get(self.pointer_to_next.checked_sub(1)?)
+    }
+
     // Use a const param b/c dynamic lookahead feels like a bad idea.
     /// Peek ahead of the next element.
     ///
@@ -85,7 +90,13 @@ impl<T> Stream<T> {
     /// e.g. 0 -> peeks next element.
     ///      1 -> peeks the element after the next one.
     pub fn peek_many<const N: usize>(&self) -> Option<&T> {
-        self.data.get(self.pointer_to_next + N)
+        self.peek_nth(N)
+    }
+
+    /// Like [`Self::peek_many`], but for when the lookahead really does need
+    /// to be dynamic.
+    pub fn peek_nth(&self, n: usize) -> Option<&T> {
+        self.data.get(self.pointer_to_next + n)
     }
 
     pub fn advance_while(&mut self, f: impl Fn(&T) -> bool) -> usize {
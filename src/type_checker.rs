@@ -0,0 +1,778 @@
+//! Hindley-Milner type inference, run after parsing and before anything is
+//! evaluated.
+//!
+//! qua is dynamically typed, so the checker is gradual: some values (like
+//! the result of `list_get`) have the type `Any`, which fits anywhere. Where
+//! different types could meet, such as the branches of an `if`, the items of
+//! a list, or the calls of a function passed as an argument, they are
+//! widened to `Any` instead of being an error. Only
+//! mismatches that would certainly fail at runtime (or that contradict an
+//! annotation) are reported.
+
+mod env;
+
+use std::collections::{BTreeMap, HashMap};
+
+pub use env::Env;
+
+use crate::{
+    ast::{
+        Annotation, BinaryExpr, BinaryOp, Binding, BindingMetadata, Block, Call, ElseBlock, Expr,
+        IfExpr, Import, LiteralValue, Match, Pattern, Program, Stmt, TypeDecl, UnaryOp,
+    },
+    lexer::Span,
+};
+
+/// Every error found, sorted by position.
+pub fn check(program: &Program, env: &mut Env) -> Vec<Error> {
+    let mut checker = Checker { errors: vec![] };
+    for stmt in program {
+        checker.check_stmt(stmt, env);
+    }
+
+    let mut errors = checker.errors;
+    errors.sort_by_key(|err| err.span.start);
+    errors
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Var(TypeVar),
+    /// Something that can't be known until runtime.
+    Any,
+    Num,
+    Str,
+    Bool,
+    Nil,
+    List(Box<Type>),
+    /// Exactly these fields. A record with different fields can still be
+    /// used in its place, since functions only use the fields they need.
+    Record(BTreeMap<String, Type>),
+    Func(Vec<Type>, Box<Type>),
+    /// A value of a `type` declaration, by its name.
+    Variant(String),
+}
+
+impl From<Annotation> for Type {
+    fn from(ty: Annotation) -> Self {
+        match ty {
+            Annotation::Num => Type::Num,
+            Annotation::Str => Type::Str,
+            Annotation::Bool => Type::Bool,
+            Annotation::Nil => Type::Nil,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TypeVar(usize);
+impl TypeVar {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// A type that can be used as any of its instances, e.g. the type of
+/// `let id(x) = x;` is `(a) -> a` for every `a`.
+#[derive(Clone, Debug)]
+pub struct Scheme {
+    vars: Vec<TypeVar>,
+    ty: Type,
+}
+
+impl Scheme {
+    /// A type that isn't generic.
+    fn mono(ty: Type) -> Self {
+        Scheme {
+            vars: Vec::new(),
+            ty,
+        }
+    }
+
+    /// Generic over every variable in it. Used for the exports of modules,
+    /// whose variables mean nothing outside of the module's env.
+    fn closed(self) -> Self {
+        let mut vars = Vec::new();
+        collect_vars(&self.ty, &mut vars);
+        Scheme { vars, ty: self.ty }
+    }
+}
+
+struct Checker {
+    errors: Vec<Error>,
+}
+
+impl Checker {
+    fn check_stmt(&mut self, stmt: &Stmt, env: &mut Env) {
+        match stmt {
+            Stmt::Let(binding) => self.check_binding(binding, env),
+            Stmt::Type(type_decl) => Self::check_type_decl(type_decl, env),
+            Stmt::Import(import) => self.check_import(import, env),
+            Stmt::Expr(expr) => {
+                self.infer(expr, env);
+            }
+        }
+    }
+
+    fn check_binding(&mut self, binding: &Binding, env: &mut Env) {
+        let ty = {
+            let mut env = env.enter_let();
+            match &binding.metadata {
+                BindingMetadata::Var => {
+                    let ty = self.infer(&binding.value, &mut env);
+                    if let Some(annotation) = &binding.annotation {
+                        self.expect_annotation(annotation, &ty, binding.value.span(), &mut env);
+                    }
+                    ty
+                }
+                BindingMetadata::Func {
+                    arguments,
+                    annotations,
+                    ..
+                } => {
+                    let Pattern::Identifier(ident) = &binding.pattern else {
+                        unreachable!("parser should only make named functions");
+                    };
+                    self.infer_func(&ident.name, arguments, annotations, binding, &mut env)
+                }
+            }
+        };
+
+        match &binding.pattern {
+            Pattern::Identifier(ident) => {
+                let scheme = env.generalize(&ty);
+                env.declare_scheme(ident.name.clone(), scheme);
+            }
+            pattern => self.bind_pattern(pattern, &ty, true, env),
+        }
+    }
+
+    fn infer_func(
+        &mut self,
+        name: &str,
+        arguments: &[Pattern],
+        annotations: &[Option<Annotation>],
+        binding: &Binding,
+        env: &mut Env,
+    ) -> Type {
+        let argument_types: Vec<_> = annotations
+            .iter()
+            .map(|annotation| match annotation {
+                Some(annotation) => Type::from(*annotation),
+                None => env.fresh(),
+            })
+            .collect();
+        let result = match &binding.annotation {
+            Some(annotation) => Type::from(*annotation),
+            None => env.fresh(),
+        };
+        let ty = Type::Func(argument_types.clone(), Box::new(result.clone()));
+
+        let mut env = env.create_scope();
+        // The function can call itself with as many arguments as it takes.
+        // Its type isn't known yet, and what it returns can depend on the
+        // arguments (like a closure object dispatching on a method name), so
+        // otherwise recursive calls can be anything.
+        let recursive = Type::Func(vec![Type::Any; arguments.len()], Box::new(Type::Any));
+        env.declare(name.to_string(), recursive);
+        for (argument, argument_type) in arguments.iter().zip(&argument_types) {
+            self.bind_pattern(argument, argument_type, true, &mut env);
+        }
+
+        let body = self.infer(&binding.value, &mut env);
+        match &binding.annotation {
+            Some(annotation) => {
+                self.expect_annotation(annotation, &body, binding.value.span(), &mut env)
+            }
+            None => self.expect(&result, &body, binding.value.span(), &mut env),
+        }
+        ty
+    }
+
+    fn check_type_decl(type_decl: &TypeDecl, env: &mut Env) {
+        for variant in &type_decl.variants {
+            let info = &variant.info;
+            let value = Type::Variant(info.type_name.clone());
+            let ty = match info.arity {
+                None => value,
+                // Types don't have parameters, so fields can be anything
+                Some(arity) => Type::Func(vec![Type::Any; arity], Box::new(value)),
+            };
+            env.declare(info.name.clone(), ty);
+        }
+    }
+
+    fn check_import(&mut self, import: &Import, env: &mut Env) {
+        let module = &import.module;
+        let exports = match env.module_exports(&module.path) {
+            Some(exports) => exports.clone(),
+            None => {
+                let mut module_env = env.module_env();
+                let errors = check(&module.program, &mut module_env);
+                let exports: Vec<_> = module
+                    .exports
+                    .fields
+                    .iter()
+                    .map(|field| {
+                        let ty = module_env.lookup(&field.name);
+                        let ty = module_env.resolve(&ty);
+                        (field.name.clone(), Scheme::mono(ty).closed())
+                    })
+                    .collect();
+                env.end_module_env(module_env);
+
+                if !errors.is_empty() {
+                    self.errors.push(Error {
+                        span: import.span,
+                        kind: ErrorKind::InModule {
+                            name: module.name.clone(),
+                            source: module.source.clone(),
+                            errors,
+                        },
+                    });
+                }
+                env.cache_module(module.path.clone(), exports.clone());
+                exports
+            }
+        };
+
+        match &import.pattern {
+            Pattern::Identifier(ident) => {
+                let fields = exports
+                    .iter()
+                    .map(|(name, scheme)| (name.clone(), env.instantiate(scheme)))
+                    .collect();
+                env.declare(ident.name.clone(), Type::Record(fields));
+            }
+            // The imported names keep their generic types
+            Pattern::Record(record) => {
+                for field in &record.fields {
+                    if let Some((_, scheme)) = exports.iter().find(|(name, _)| *name == field.name)
+                    {
+                        env.declare_scheme(field.name.clone(), scheme.clone());
+                    }
+                }
+            }
+            _ => unreachable!("parser should only make name or record import patterns"),
+        }
+    }
+
+    fn infer(&mut self, expr: &Expr, env: &mut Env) -> Type {
+        match expr {
+            Expr::Block(block) => self.infer_block(block, env),
+            Expr::Call(call) => self.infer_call(call, env),
+            Expr::If(if_expr) => self.infer_if(if_expr, env),
+            Expr::Match(match_expr) => self.infer_match(match_expr, env),
+            Expr::Binary(binary_expr) => self.infer_binary(binary_expr, env),
+            Expr::Unary(unary_expr) => {
+                let rhs = self.infer(&unary_expr.rhs, env);
+                match unary_expr.op {
+                    // Anything can be truthy or falsy
+                    UnaryOp::Not => Type::Bool,
+                    UnaryOp::Negate => {
+                        self.expect_value(&Type::Num, &rhs, &unary_expr.rhs, env);
+                        Type::Num
+                    }
+                }
+            }
+            Expr::Literal(literal) => match literal.value {
+                LiteralValue::Bool(_) => Type::Bool,
+                LiteralValue::Number(_) => Type::Num,
+                LiteralValue::Str(_) => Type::Str,
+                LiteralValue::Nil => Type::Nil,
+            },
+            Expr::Interpolated(interpolated) => {
+                // Anything can be converted to a string
+                for part in &interpolated.parts {
+                    self.infer(part, env);
+                }
+                Type::Str
+            }
+            Expr::List(list) => {
+                let mut item = None;
+                for expr in &list.items {
+                    let ty = self.infer(expr, env);
+                    item = Some(join(item, &ty, env));
+                }
+                let item = item.unwrap_or_else(|| env.fresh());
+                Type::List(Box::new(item))
+            }
+            Expr::Index(index) => {
+                let item = env.fresh();
+                let target = self.infer(&index.target, env);
+                self.expect(
+                    &Type::List(Box::new(item.clone())),
+                    &target,
+                    index.target.span(),
+                    env,
+                );
+                let i = self.infer(&index.index, env);
+                self.expect_value(&Type::Num, &i, &index.index, env);
+                item
+            }
+            Expr::Record(record) => {
+                let fields = record
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.clone(), self.infer(&field.value, env)))
+                    .collect::<Vec<_>>();
+                let Some(base) = &record.base else {
+                    return Type::Record(fields.into_iter().collect());
+                };
+
+                let base_ty = self.infer(base, env);
+                match env.shallow_resolve(&base_ty) {
+                    Type::Record(mut base_fields) => {
+                        for ((name, ty), field) in fields.into_iter().zip(&record.fields) {
+                            // Updates can't add new fields
+                            if !base_fields.contains_key(&name) {
+                                self.no_such_field(&name, &base_ty, field.span, env);
+                            }
+                            base_fields.insert(name, ty);
+                        }
+                        Type::Record(base_fields)
+                    }
+                    Type::Var(_) | Type::Any => Type::Any,
+                    _ => {
+                        self.not_a_record(&base_ty, base.span(), env);
+                        Type::Any
+                    }
+                }
+            }
+            Expr::Field(field_access) => {
+                let target = self.infer(&field_access.target, env);
+                match env.shallow_resolve(&target) {
+                    Type::Record(fields) => match fields.get(&field_access.field) {
+                        Some(ty) => ty.clone(),
+                        None => {
+                            self.no_such_field(
+                                &field_access.field,
+                                &target,
+                                field_access.span,
+                                env,
+                            );
+                            Type::Any
+                        }
+                    },
+                    // Records can have any fields, so there's nothing to
+                    // infer from this
+                    Type::Var(_) | Type::Any => Type::Any,
+                    _ => {
+                        self.not_a_record(&target, field_access.target.span(), env);
+                        Type::Any
+                    }
+                }
+            }
+            Expr::Identifier(identifier) => env.lookup(&identifier.name),
+        }
+    }
+
+    fn infer_block(&mut self, block: &Block, env: &mut Env) -> Type {
+        let mut env = env.create_scope();
+        for stmt in &block.stmts {
+            self.check_stmt(stmt, &mut env);
+        }
+        match &block.return_expr {
+            Some(expr) => self.infer(expr, &mut env),
+            None => Type::Nil,
+        }
+    }
+
+    fn infer_call(&mut self, call: &Call, env: &mut Env) -> Type {
+        let target = self.infer(&call.target, env);
+        let arguments: Vec<_> = call
+            .arguments
+            .iter()
+            .map(|argument| (self.infer(argument, env), argument))
+            .collect();
+
+        match env.shallow_resolve(&target) {
+            Type::Func(parameters, result) => {
+                if arguments.len() != parameters.len() {
+                    self.errors.push(Error {
+                        span: call.span,
+                        kind: ErrorKind::WrongNumberOfArguments {
+                            expected: parameters.len(),
+                            given: arguments.len(),
+                        },
+                    });
+                }
+                for (parameter, (argument, expr)) in parameters.iter().zip(&arguments) {
+                    self.expect_value(parameter, argument, expr, env);
+                }
+                *result
+            }
+            // Something like an unannotated argument, which could be a
+            // different function each time (or a generic one, like `id`),
+            // so different calls of it can take and return different types
+            Type::Var(_) => {
+                let func = Type::Func(vec![Type::Any; arguments.len()], Box::new(Type::Any));
+                self.expect(&func, &target, call.target.span(), env);
+                Type::Any
+            }
+            Type::Any => Type::Any,
+            _ => {
+                self.errors.push(Error {
+                    span: call.target.span(),
+                    kind: ErrorKind::NotAFunction {
+                        found: normalize(&[env.resolve(&target)]).remove(0),
+                    },
+                });
+                Type::Any
+            }
+        }
+    }
+
+    fn infer_if(&mut self, if_expr: &IfExpr, env: &mut Env) -> Type {
+        // Anything can be truthy or falsy
+        self.infer(&if_expr.condition, env);
+        let then_type = self.infer_block(&if_expr.then_block, env);
+        let else_type = match &if_expr.else_block {
+            Some(ElseBlock::ElseIf(else_if)) => self.infer_if(else_if, env),
+            Some(ElseBlock::Else(block)) => self.infer_block(block, env),
+            None => Type::Nil,
+        };
+        join(Some(then_type), &else_type, env)
+    }
+
+    fn infer_match(&mut self, match_expr: &Match, env: &mut Env) -> Type {
+        let value = self.infer(&match_expr.value, env);
+        // Each arm can expect a different type, so an unknown value stays
+        // unknown
+        let value = match env.shallow_resolve(&value) {
+            Type::Var(_) => Type::Any,
+            value => value,
+        };
+        let mut result = None;
+        for arm in &match_expr.arms {
+            let mut env = env.create_scope();
+            self.bind_pattern(&arm.pattern, &value, false, &mut env);
+            if let Some(guard) = &arm.guard {
+                self.infer(guard, &mut env);
+            }
+            let body = self.infer(&arm.body, &mut env);
+            result = Some(join(result, &body, &env));
+        }
+        result.unwrap_or(Type::Nil)
+    }
+
+    fn infer_binary(&mut self, binary_expr: &BinaryExpr, env: &mut Env) -> Type {
+        let (lhs_span, rhs_span) = (binary_expr.lhs.span(), binary_expr.rhs.span());
+        let lhs = self.infer(&binary_expr.lhs, env);
+        let rhs = self.infer(&binary_expr.rhs, env);
+        let nums = |checker: &mut Self, env: &mut Env| {
+            checker.expect_value(&Type::Num, &lhs, &binary_expr.lhs, env);
+            checker.expect_value(&Type::Num, &rhs, &binary_expr.rhs, env);
+        };
+        match binary_expr.op {
+            // Evaluates to one side or the other
+            BinaryOp::Or | BinaryOp::And => join(Some(lhs.clone()), &rhs, env),
+            // Anything can be compared for equality
+            BinaryOp::Eq | BinaryOp::NotEq => Type::Bool,
+            BinaryOp::Greater | BinaryOp::GreaterEq | BinaryOp::Less | BinaryOp::LessEq => {
+                nums(self, env);
+                Type::Bool
+            }
+            BinaryOp::Subtract | BinaryOp::Divide | BinaryOp::Multiply => {
+                nums(self, env);
+                Type::Num
+            }
+            // Adds numbers, or concatenates if either side is a string. With
+            // an unknown side, it could be either.
+            BinaryOp::Add => {
+                let lhs = env.shallow_resolve(&lhs);
+                let rhs = env.shallow_resolve(&rhs);
+                for (ty, span) in [(&lhs, lhs_span), (&rhs, rhs_span)] {
+                    if !matches!(ty, Type::Num | Type::Str | Type::Var(_) | Type::Any) {
+                        self.errors.push(Error {
+                            span,
+                            kind: ErrorKind::NotAddable {
+                                found: normalize(&[env.resolve(ty)]).remove(0),
+                            },
+                        });
+                    }
+                }
+                match (lhs, rhs) {
+                    (Type::Num, Type::Num) => Type::Num,
+                    (Type::Str, _) | (_, Type::Str) => Type::Str,
+                    _ => Type::Any,
+                }
+            }
+        }
+    }
+
+    /// Declares the names bound by `pattern`, when matched against a value of
+    /// type `ty`. If the pattern is `refutable` (like in a `match` arm), it
+    /// doesn't have to fit the type, since it might just not match.
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: &Type, strict: bool, env: &mut Env) {
+        let fits = |checker: &mut Self, expected: &Type, env: &mut Env| {
+            if strict {
+                checker.expect(expected, ty, pattern.span(), env);
+            } else if env.unify(expected, ty).is_err() {
+                // Only the names bound by the pattern matter now
+                return false;
+            }
+            true
+        };
+
+        match pattern {
+            Pattern::Identifier(ident) => env.declare(ident.name.clone(), ty.clone()),
+            Pattern::Wildcard(_) | Pattern::Literal(_) => {}
+            Pattern::List(list) => {
+                let item = env.fresh();
+                let list_type = Type::List(Box::new(item.clone()));
+                let item = if fits(self, &list_type, env) {
+                    item
+                } else {
+                    Type::Any
+                };
+                for pattern in &list.items {
+                    self.bind_pattern(pattern, &item, strict, env);
+                }
+                if let Some(rest) = &list.rest {
+                    self.bind_pattern(rest, &Type::List(Box::new(item)), strict, env);
+                }
+            }
+            Pattern::Record(record) => {
+                let resolved = env.shallow_resolve(ty);
+                for field in &record.fields {
+                    let field_type = match &resolved {
+                        Type::Record(fields) => match fields.get(&field.name) {
+                            Some(ty) => ty.clone(),
+                            None => {
+                                if strict {
+                                    self.no_such_field(&field.name, ty, field.span, env);
+                                }
+                                Type::Any
+                            }
+                        },
+                        Type::Var(_) | Type::Any => Type::Any,
+                        _ => {
+                            if strict {
+                                self.not_a_record(ty, record.span, env);
+                            }
+                            Type::Any
+                        }
+                    };
+                    self.bind_pattern(&field.pattern, &field_type, strict, env);
+                }
+            }
+            Pattern::Variant(variant) => {
+                fits(self, &Type::Variant(variant.info.type_name.clone()), env);
+                // Types don't have parameters, so fields can be anything
+                for field in &variant.fields {
+                    self.bind_pattern(field, &Type::Any, strict, env);
+                }
+            }
+        }
+    }
+
+    /// Unify the types, or report that `found` isn't `expected`.
+    fn expect(&mut self, expected: &Type, found: &Type, span: Span, env: &mut Env) {
+        if env.unify(expected, found).is_err() {
+            let mut types = normalize(&[env.resolve(expected), env.resolve(found)]);
+            let found = types.remove(1);
+            let expected = types.remove(0);
+            self.errors.push(Error {
+                span,
+                kind: ErrorKind::Mismatch { expected, found },
+            });
+        }
+    }
+
+    /// Like `expect`, for the value of `expr`. A `nil` that comes from an
+    /// `if` without an `else` (or a block without a final expression) gets
+    /// its own error, since the `nil` isn't written anywhere.
+    fn expect_value(&mut self, expected: &Type, found: &Type, expr: &Expr, env: &mut Env) {
+        let implicit_nil =
+            is_implicit_nil(expr) && matches!(env.shallow_resolve(found), Type::Nil);
+        if implicit_nil && env.unify(expected, found).is_err() {
+            self.errors.push(Error {
+                span: expr.span(),
+                kind: ErrorKind::ImplicitNil {
+                    expected: normalize(&[env.resolve(expected)]).remove(0),
+                },
+            });
+        } else {
+            self.expect(expected, found, expr.span(), env);
+        }
+    }
+
+    fn expect_annotation(
+        &mut self,
+        annotation: &Annotation,
+        found: &Type,
+        span: Span,
+        env: &mut Env,
+    ) {
+        let expected = Type::from(*annotation);
+        if env.unify(&expected, found).is_err() {
+            self.errors.push(Error {
+                span,
+                kind: ErrorKind::AnnotationMismatch {
+                    expected,
+                    found: normalize(&[env.resolve(found)]).remove(0),
+                },
+            });
+        }
+    }
+
+    fn no_such_field(&mut self, field: &str, record: &Type, span: Span, env: &mut Env) {
+        self.errors.push(Error {
+            span,
+            kind: ErrorKind::NoSuchField {
+                field: field.to_string(),
+                record: normalize(&[env.resolve(record)]).remove(0),
+            },
+        });
+    }
+
+    fn not_a_record(&mut self, found: &Type, span: Span, env: &mut Env) {
+        self.errors.push(Error {
+            span,
+            kind: ErrorKind::NotARecord {
+                found: normalize(&[env.resolve(found)]).remove(0),
+            },
+        });
+    }
+}
+
+/// Whether `expr` evaluates to `nil` without it being written, like an `if`
+/// without an `else`.
+fn is_implicit_nil(expr: &Expr) -> bool {
+    match expr {
+        Expr::Block(block) => block_is_implicit_nil(block),
+        Expr::If(if_expr) => if_is_implicit_nil(if_expr),
+        _ => false,
+    }
+}
+
+fn block_is_implicit_nil(block: &Block) -> bool {
+    block.return_expr.as_deref().is_none_or(is_implicit_nil)
+}
+
+/// Whether any branch of the `if` is an implicit `nil`, including the
+/// missing `else`.
+fn if_is_implicit_nil(if_expr: &IfExpr) -> bool {
+    block_is_implicit_nil(&if_expr.then_block)
+        || match &if_expr.else_block {
+            None => true,
+            Some(ElseBlock::ElseIf(else_if)) => if_is_implicit_nil(else_if),
+            Some(ElseBlock::Else(else_block)) => block_is_implicit_nil(else_block),
+        }
+}
+
+/// The type of something that is either `a` or `b` (if there is an `a`). If
+/// they aren't already the same type, it could be anything. Unlike `unify`,
+/// this never decides what an unknown type is, since the values of
+/// different branches are allowed to have different types.
+fn join(a: Option<Type>, b: &Type, env: &Env) -> Type {
+    match a {
+        Some(a) if env.same(&a, b) => a,
+        Some(_) => Type::Any,
+        None => b.clone(),
+    }
+}
+
+/// Renumbers the variables in `types` from 0, in the order they first
+/// appear, so that they can be displayed as `a`, `b`, ...
+fn normalize(types: &[Type]) -> Vec<Type> {
+    let mut vars = Vec::new();
+    for ty in types {
+        collect_vars(ty, &mut vars);
+    }
+    let renamed: HashMap<_, _> = vars
+        .into_iter()
+        .enumerate()
+        .map(|(i, var)| (var, TypeVar(i)))
+        .collect();
+    types.iter().map(|ty| rename(ty, &renamed)).collect()
+}
+
+fn rename(ty: &Type, vars: &HashMap<TypeVar, TypeVar>) -> Type {
+    match ty {
+        Type::Var(var) => Type::Var(vars[var]),
+        Type::List(item) => Type::List(Box::new(rename(item, vars))),
+        Type::Record(fields) => Type::Record(
+            fields
+                .iter()
+                .map(|(name, ty)| (name.clone(), rename(ty, vars)))
+                .collect(),
+        ),
+        Type::Func(args, ret) => Type::Func(
+            args.iter().map(|ty| rename(ty, vars)).collect(),
+            Box::new(rename(ret, vars)),
+        ),
+        ty => ty.clone(),
+    }
+}
+
+fn collect_vars(ty: &Type, vars: &mut Vec<TypeVar>) {
+    match ty {
+        Type::Var(var) => {
+            if !vars.contains(var) {
+                vars.push(*var);
+            }
+        }
+        Type::List(item) => collect_vars(item, vars),
+        Type::Record(fields) => {
+            for ty in fields.values() {
+                collect_vars(ty, vars);
+            }
+        }
+        Type::Func(args, ret) => {
+            for ty in args {
+                collect_vars(ty, vars);
+            }
+            collect_vars(ret, vars);
+        }
+        Type::Any | Type::Num | Type::Str | Type::Bool | Type::Nil | Type::Variant(_) => {}
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub span: Span,
+    pub kind: ErrorKind,
+}
+#[derive(Debug)]
+pub enum ErrorKind {
+    Mismatch {
+        expected: Type,
+        found: Type,
+    },
+    /// `nil` from an `if` without an `else`, or a block without a final
+    /// expression, where something else was expected.
+    ImplicitNil {
+        expected: Type,
+    },
+    /// A value that doesn't have the type it is annotated with.
+    AnnotationMismatch {
+        expected: Type,
+        found: Type,
+    },
+    NotAFunction {
+        found: Type,
+    },
+    NotARecord {
+        found: Type,
+    },
+    /// One side of a `+` that is neither a number nor a string.
+    NotAddable {
+        found: Type,
+    },
+    NoSuchField {
+        field: String,
+        record: Type,
+    },
+    WrongNumberOfArguments {
+        expected: usize,
+        given: usize,
+    },
+    /// Errors in an imported module.
+    InModule {
+        name: String,
+        source: String,
+        errors: Vec<Error>,
+    },
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+};
+
+use super::{Scheme, Type, TypeVar};
+use crate::util::nonempty_vec::NEVec;
+
+#[derive(Clone)]
+pub struct Env {
+    scopes: NEVec<HashMap<String, Scheme>>,
+    /// What each type variable has been unified with, if anything.
+    vars: Vec<Var>,
+    /// How many `let`s deep the checker is. Variables created deeper than
+    /// the current level aren't referenced by anything in scope, so they can
+    /// be generalized.
+    level: usize,
+    /// The exports of every module that has been checked, by canonical path.
+    modules: HashMap<PathBuf, Vec<(String, Scheme)>>,
+    /// Functions the host registered, which modules can see too.
+    host: Vec<(String, Type)>,
+}
+
+#[derive(Clone, Debug)]
+enum Var {
+    Unbound { level: usize },
+    Bound(Type),
+}
+
+impl Env {
+    pub fn new() -> Self {
+        let mut env = Env {
+            scopes: NEVec::default(),
+            vars: Vec::new(),
+            level: 0,
+            modules: HashMap::new(),
+            host: Vec::new(),
+        };
+        crate::interperter::type_stdlib(&mut env);
+        // Keep user-defined names separate, like in `parser::Env`
+        env.scopes.push(HashMap::new());
+        env
+    }
+
+    /// Gives `name` the type `ty` in the current scope. Type variables in it
+    /// are not generalized.
+    pub fn declare(&mut self, name: String, ty: Type) {
+        self.declare_scheme(name, Scheme::mono(ty));
+    }
+
+    /// Declares a function the host registered, which imported modules can
+    /// call too.
+    pub fn declare_host(&mut self, name: String, ty: Type) {
+        self.declare(name.clone(), ty.clone());
+        self.host.push((name, ty));
+    }
+
+    pub fn declare_scheme(&mut self, name: String, scheme: Scheme) {
+        self.scopes.last_mut().insert(name, scheme);
+    }
+
+    /// A fresh instance of the type of `name`. Names the checker doesn't know
+    /// about can be anything.
+    pub fn lookup(&mut self, name: &str) -> Type {
+        match self.scheme(name) {
+            Some(scheme) => self.instantiate(&scheme),
+            None => Type::Any,
+        }
+    }
+
+    fn scheme(&self, name: &str) -> Option<Scheme> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+    }
+
+    pub fn fresh(&mut self) -> Type {
+        let var = TypeVar(self.vars.len());
+        self.vars.push(Var::Unbound { level: self.level });
+        Type::Var(var)
+    }
+
+    /// Makes `a` and `b` the same type, if they can be. If they can't be,
+    /// nothing is changed.
+    pub(super) fn unify(&mut self, a: &Type, b: &Type) -> Result<(), ()> {
+        let snapshot = self.vars.clone();
+        let result = self.unify_inner(a, b);
+        if result.is_err() {
+            self.vars = snapshot;
+        }
+        result
+    }
+
+    fn unify_inner(&mut self, a: &Type, b: &Type) -> Result<(), ()> {
+        let (a, b) = (self.shallow_resolve(a), self.shallow_resolve(b));
+        match (&a, &b) {
+            (Type::Any, _) | (_, Type::Any) => Ok(()),
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => self.bind(*var, ty),
+            (Type::Num, Type::Num)
+            | (Type::Str, Type::Str)
+            | (Type::Bool, Type::Bool)
+            | (Type::Nil, Type::Nil) => Ok(()),
+            (Type::List(a), Type::List(b)) => self.unify_inner(a, b),
+            (Type::Record(a), Type::Record(b)) => {
+                // Different shapes could both work, so nothing is known
+                // about how their fields relate
+                if !a.keys().eq(b.keys()) {
+                    return Ok(());
+                }
+                for (a, b) in a.values().zip(b.values()) {
+                    self.unify_inner(a, b)?;
+                }
+                Ok(())
+            }
+            (Type::Func(a_args, a_ret), Type::Func(b_args, b_ret))
+                if a_args.len() == b_args.len() =>
+            {
+                for (a, b) in a_args.iter().zip(b_args) {
+                    self.unify_inner(a, b)?;
+                }
+                self.unify_inner(a_ret, b_ret)
+            }
+            (Type::Variant(a), Type::Variant(b)) if a == b => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    fn bind(&mut self, var: TypeVar, ty: &Type) -> Result<(), ()> {
+        let Var::Unbound { level } = self.vars[var.0] else {
+            unreachable!("resolved type var should be unbound");
+        };
+        if self.occurs(var, level, ty) {
+            // The type would have to contain itself, like a function that
+            // returns itself. That's fine at runtime, but it can't be
+            // written down, so leave it unknown.
+            return Ok(());
+        }
+        self.vars[var.0] = Var::Bound(ty.clone());
+        Ok(())
+    }
+
+    /// Whether `var` is in `ty`. Also lowers the level of the variables in
+    /// `ty` to `level`, since they are now as visible as `var` is.
+    fn occurs(&mut self, var: TypeVar, level: usize, ty: &Type) -> bool {
+        match self.shallow_resolve(ty) {
+            Type::Var(other) if other == var => true,
+            Type::Var(other) => {
+                if let Var::Unbound { level: other_level } = &mut self.vars[other.0] {
+                    *other_level = (*other_level).min(level);
+                }
+                false
+            }
+            Type::List(item) => self.occurs(var, level, &item),
+            Type::Record(fields) => fields.values().any(|ty| self.occurs(var, level, ty)),
+            Type::Func(args, ret) => {
+                args.iter().any(|ty| self.occurs(var, level, ty)) || self.occurs(var, level, &ret)
+            }
+            Type::Any | Type::Num | Type::Str | Type::Bool | Type::Nil | Type::Variant(_) => false,
+        }
+    }
+
+    /// Whether `a` and `b` are already known to be the same type, without
+    /// unifying anything.
+    pub fn same(&self, a: &Type, b: &Type) -> bool {
+        match (self.shallow_resolve(a), self.shallow_resolve(b)) {
+            (Type::List(a), Type::List(b)) => self.same(&a, &b),
+            (Type::Record(a), Type::Record(b)) => {
+                a.keys().eq(b.keys()) && a.values().zip(b.values()).all(|(a, b)| self.same(a, b))
+            }
+            (Type::Func(a_args, a_ret), Type::Func(b_args, b_ret)) => {
+                a_args.len() == b_args.len()
+                    && a_args.iter().zip(&b_args).all(|(a, b)| self.same(a, b))
+                    && self.same(&a_ret, &b_ret)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// Follows bound variables until reaching a type that isn't one.
+    pub fn shallow_resolve(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(var) = ty {
+            match &self.vars[var.0] {
+                Var::Bound(bound) => ty = bound.clone(),
+                Var::Unbound { .. } => break,
+            }
+        }
+        ty
+    }
+
+    /// `ty`, with every bound variable (however deeply nested) replaced by
+    /// what it is bound to.
+    pub fn resolve(&self, ty: &Type) -> Type {
+        match self.shallow_resolve(ty) {
+            Type::List(item) => Type::List(Box::new(self.resolve(&item))),
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(name, ty)| (name.clone(), self.resolve(ty)))
+                    .collect(),
+            ),
+            Type::Func(args, ret) => Type::Func(
+                args.iter().map(|ty| self.resolve(ty)).collect(),
+                Box::new(self.resolve(&ret)),
+            ),
+            ty => ty,
+        }
+    }
+
+    /// A scheme where every variable in `ty` that was created since the
+    /// matching `enter_let` can be anything.
+    pub fn generalize(&self, ty: &Type) -> Scheme {
+        let ty = self.resolve(ty);
+        let mut vars = Vec::new();
+        self.collect_generic(&ty, &mut vars);
+        Scheme { vars, ty }
+    }
+
+    fn collect_generic(&self, ty: &Type, vars: &mut Vec<TypeVar>) {
+        match ty {
+            Type::Var(var) => {
+                let Var::Unbound { level } = self.vars[var.0] else {
+                    unreachable!("type should be resolved");
+                };
+                if level > self.level && !vars.contains(var) {
+                    vars.push(*var);
+                }
+            }
+            Type::List(item) => self.collect_generic(item, vars),
+            Type::Record(fields) => {
+                for ty in fields.values() {
+                    self.collect_generic(ty, vars);
+                }
+            }
+            Type::Func(args, ret) => {
+                for ty in args {
+                    self.collect_generic(ty, vars);
+                }
+                self.collect_generic(ret, vars);
+            }
+            Type::Any | Type::Num | Type::Str | Type::Bool | Type::Nil | Type::Variant(_) => {}
+        }
+    }
+
+    pub fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: HashMap<_, _> = scheme.vars.iter().map(|var| (*var, self.fresh())).collect();
+        substitute(&scheme.ty, &fresh)
+    }
+
+    /// Types created until the guard is dropped can be generalized.
+    pub fn enter_let(&mut self) -> LetGuard<'_> {
+        LetGuard::new(self)
+    }
+
+    pub fn create_scope(&mut self) -> ScopeGuard<'_> {
+        ScopeGuard::new(self)
+    }
+
+    pub fn module_exports(&self, path: &PathBuf) -> Option<&Vec<(String, Scheme)>> {
+        self.modules.get(path)
+    }
+
+    /// A fresh env to check a module in. It shares the modules that have
+    /// already been checked, and must be given back with `end_module_env`.
+    pub fn module_env(&mut self) -> Env {
+        let mut env = Env::new();
+        for (name, ty) in &self.host {
+            env.declare_host(name.clone(), ty.clone());
+        }
+        env.modules = std::mem::take(&mut self.modules);
+        env
+    }
+
+    pub fn end_module_env(&mut self, module_env: Env) {
+        self.modules = module_env.modules;
+    }
+
+    pub fn cache_module(&mut self, path: PathBuf, exports: Vec<(String, Scheme)>) {
+        self.modules.insert(path, exports);
+    }
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn substitute(ty: &Type, vars: &HashMap<TypeVar, Type>) -> Type {
+    match ty {
+        Type::Var(var) => vars.get(var).cloned().unwrap_or(Type::Var(*var)),
+        Type::List(item) => Type::List(Box::new(substitute(item, vars))),
+        Type::Record(fields) => Type::Record(
+            fields
+                .iter()
+                .map(|(name, ty)| (name.clone(), substitute(ty, vars)))
+                .collect::<BTreeMap<_, _>>(),
+        ),
+        Type::Func(args, ret) => Type::Func(
+            args.iter().map(|ty| substitute(ty, vars)).collect(),
+            Box::new(substitute(ret, vars)),
+        ),
+        ty => ty.clone(),
+    }
+}
+
+#[clippy::has_significant_drop]
+pub struct LetGuard<'a>(&'a mut Env);
+impl<'a> LetGuard<'a> {
+    fn new(env: &'a mut Env) -> Self {
+        env.level += 1;
+        LetGuard(env)
+    }
+}
+impl Deref for LetGuard<'_> {
+    type Target = Env;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+impl DerefMut for LetGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+    }
+}
+impl Drop for LetGuard<'_> {
+    fn drop(&mut self) {
+        self.level -= 1;
+    }
+}
+
+#[clippy::has_significant_drop]
+pub struct ScopeGuard<'a>(&'a mut Env);
+impl<'a> ScopeGuard<'a> {
+    fn new(env: &'a mut Env) -> Self {
+        env.scopes.push(HashMap::new());
+        ScopeGuard(env)
+    }
+}
+impl Deref for ScopeGuard<'_> {
+    type Target = Env;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+impl DerefMut for ScopeGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+    }
+}
+impl Drop for ScopeGuard<'_> {
+    fn drop(&mut self) {
+        self.scopes.pop_unchecked();
+    }
+}
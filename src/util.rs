@@ -1 +1,2 @@
 pub mod nonempty_vec;
+pub mod persistent_vec;
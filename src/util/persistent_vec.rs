@@ -0,0 +1,155 @@
+use std::{fmt, rc::Rc};
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+/// A persistent Vec. Cloning is O(1), and a clone shares everything with the
+/// original until one of them is changed, which only copies the nodes on the
+/// path to the item (O(log n)).
+///
+/// Items are stored in a tree with up to 32 children per node, where the
+/// index of an item picks the child at each level (5 bits at a time).
+pub struct PVec<T> {
+    len: usize,
+    /// How far to shift an index to get the child of the root it is in.
+    /// Always a multiple of `BITS`, and 0 when the root is a leaf.
+    shift: u32,
+    root: Rc<Node<T>>,
+}
+
+#[derive(Clone)]
+enum Node<T> {
+    Branch(Vec<Rc<Node<T>>>),
+    Leaf(Vec<T>),
+}
+
+impl<T: Clone> PVec<T> {
+    pub fn new() -> Self {
+        PVec {
+            len: 0,
+            shift: 0,
+            root: Rc::new(Node::Leaf(Vec::new())),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        let mut node = &*self.root;
+        let mut shift = self.shift;
+        loop {
+            match node {
+                Node::Branch(children) => {
+                    node = &children[(index >> shift) & MASK];
+                    shift -= BITS;
+                }
+                Node::Leaf(items) => return items.get(index & MASK),
+            }
+        }
+    }
+
+    /// Copies the nodes on the way to the item, unless nothing else shares
+    /// them.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+        let mut node = Rc::make_mut(&mut self.root);
+        let mut shift = self.shift;
+        loop {
+            match node {
+                Node::Branch(children) => {
+                    node = Rc::make_mut(&mut children[(index >> shift) & MASK]);
+                    shift -= BITS;
+                }
+                Node::Leaf(items) => return items.get_mut(index & MASK),
+            }
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        // The tree is full, so it needs another level
+        if self.len == 1 << (self.shift + BITS) {
+            let old_root = std::mem::replace(&mut self.root, Rc::new(Node::Branch(Vec::new())));
+            self.root = Rc::new(Node::Branch(vec![old_root]));
+            self.shift += BITS;
+        }
+
+        let index = self.len;
+        let mut node = Rc::make_mut(&mut self.root);
+        let mut shift = self.shift;
+        loop {
+            match node {
+                Node::Branch(children) => {
+                    let child = (index >> shift) & MASK;
+                    if child == children.len() {
+                        children.push(Rc::new(if shift == BITS {
+                            Node::Leaf(Vec::with_capacity(WIDTH))
+                        } else {
+                            Node::Branch(Vec::new())
+                        }));
+                    }
+                    node = Rc::make_mut(&mut children[child]);
+                    shift -= BITS;
+                }
+                Node::Leaf(items) => {
+                    items.push(value);
+                    break;
+                }
+            }
+        }
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        (0..self.len).map(|i| self.get(i).expect("index is in bounds"))
+    }
+}
+
+impl<T: Clone> Default for PVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for PVec<T> {
+    fn clone(&self) -> Self {
+        PVec {
+            len: self.len,
+            shift: self.shift,
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl<T: Clone> FromIterator<T> for PVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = PVec::new();
+        for value in iter {
+            vec.push(value);
+        }
+        vec
+    }
+}
+
+impl<T: Clone + PartialEq> PartialEq for PVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for PVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,455 @@
+//! A bytecode compiler, and a stack-based virtual machine to run it. It can
+//! be used instead of the tree-walking interpreter, which clones the AST of
+//! every function it defines.
+//!
+//! Values, native functions and errors are all shared with the interpreter.
+//! Locals are kept on their own stack (separate from temporaries), so the
+//! `IdentLocation`s that the parser resolved can be used as is.
+
+mod chunk;
+mod compiler;
+mod env;
+
+use std::rc::Rc;
+
+pub use chunk::Closure;
+pub use env::Env;
+
+use crate::{
+    ast::{BinaryOp, IdentLocation, Module, Program},
+    interperter::{
+        add, construct, get_index, Arity, Ctx, Destructure, DiagnosticType, Error, ErrorKind, Func,
+        Result, TraceFrame, Value,
+    },
+    lexer::Span,
+};
+use chunk::{Op, Proto};
+
+pub fn run(program: &Program, env: &mut Env) -> Result<Value> {
+    let proto = compiler::compile_program(program, env.locals.len());
+    Vm::new(env).execute(proto)
+}
+
+/// Calls `func` from Rust code, in the top level of `env`.
+pub fn call(func: Func, arguments: Vec<Value>, env: &mut Env) -> Result<Value> {
+    let closure = match func {
+        Func::Compiled(closure) => closure,
+        Func::Native(func) => return func.call(&mut Ctx::vm(env), arguments),
+        Func::Constructor(info) => return construct(&info, arguments),
+        func @ Func::User(_) => {
+            return Err(Error::new(ErrorKind::ForeignFunc {
+                func: Value::Func(func).to_string(),
+            }))
+        }
+    };
+    Arity::exact(closure.proto.arity).check(arguments.len())?;
+
+    let base = env.locals.len();
+    env.locals
+        .push(Value::Func(Func::Compiled(closure.clone())));
+    env.locals.extend(arguments);
+    let frame = Frame {
+        closure,
+        ip: 0,
+        base,
+        stack_base: 0,
+        call_site: None,
+        tail_calls: 0,
+    };
+    let result = Vm::new(env).run_frame(frame);
+    env.locals.truncate(base);
+    result
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    /// The next op to run.
+    ip: usize,
+    /// Where the frame's locals start.
+    base: usize,
+    /// How many temporaries were on the stack when the frame was entered.
+    stack_base: usize,
+    /// Where the function was called, if it isn't the top level.
+    call_site: Option<Span>,
+    /// How many times the frame has been reused for a tail call.
+    tail_calls: usize,
+}
+
+impl Frame {
+    fn span(&self, i: u32) -> Span {
+        self.closure.proto.chunk.spans[i as usize]
+    }
+}
+
+struct Vm<'a> {
+    env: &'a mut Env,
+    /// Temporaries, like the arguments of a call before it is made.
+    stack: Vec<Value>,
+    /// The callers of the frame being run.
+    frames: Vec<Frame>,
+}
+
+impl<'a> Vm<'a> {
+    fn new(env: &'a mut Env) -> Self {
+        Vm {
+            env,
+            stack: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Runs `proto` at the top level. Its locals stay defined afterwards.
+    fn execute(&mut self, proto: Rc<Proto>) -> Result<Value> {
+        self.run_frame(Frame {
+            closure: Rc::new(Closure {
+                proto,
+                upvalues: Vec::new(),
+            }),
+            ip: 0,
+            base: 0,
+            stack_base: 0,
+            call_site: None,
+            tail_calls: 0,
+        })
+    }
+
+    /// Runs until `frame` returns.
+    fn run_frame(&mut self, mut frame: Frame) -> Result<Value> {
+        loop {
+            match self.step(&mut frame) {
+                Ok(None) => {}
+                Ok(Some(value)) => return Ok(value),
+                Err(error) => {
+                    // Undefine the locals of every function being called
+                    let first_call = match self.frames.len() {
+                        0 => None,
+                        1 => Some(&frame),
+                        _ => Some(&self.frames[1]),
+                    };
+                    if let Some(first_call) = first_call {
+                        self.env.locals.truncate(first_call.base);
+                    }
+                    let trace = std::iter::once(&frame)
+                        .chain(self.frames.iter().rev())
+                        .filter_map(|frame| {
+                            Some(TraceFrame {
+                                name: frame.closure.proto.name.clone(),
+                                call_site: frame.call_site?,
+                                tail_calls: frame.tail_calls,
+                            })
+                        });
+                    return Err(trace.fold(error, Error::in_frame));
+                }
+            }
+        }
+    }
+
+    /// Runs a single op. Returns the value of the top-level frame once it
+    /// returns.
+    fn step(&mut self, frame: &mut Frame) -> Result<Option<Value>> {
+        let op = frame.closure.proto.chunk.code[frame.ip];
+        frame.ip += 1;
+
+        match op {
+            Op::Const(i) => {
+                let value = frame.closure.proto.chunk.constants[i as usize].clone();
+                self.stack.push(value);
+            }
+            Op::Nil => self.stack.push(Value::Nil),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::PopUnder => {
+                // Moves the top into its place
+                self.stack.swap_remove(self.stack.len() - 2);
+            }
+
+            Op::GetLocal(i) => {
+                let value = self.env.locals[frame.base + i as usize].clone();
+                self.stack.push(value);
+            }
+            Op::GetUpvalue(i) => {
+                let value = frame.closure.upvalues[i as usize].clone();
+                self.stack.push(value);
+            }
+            Op::Define => {
+                let value = self.pop();
+                self.env.locals.push(value);
+            }
+            Op::Destructure(i) => {
+                let value = self.pop();
+                let pattern = &frame.closure.proto.chunk.patterns[i as usize];
+                let mut bound = Vec::new();
+                if !pattern.destructure(&value, &mut bound) {
+                    return Err(Error::new(ErrorKind::PatternMismatch {
+                        value: value.to_string(),
+                    })
+                    .span(pattern.span()));
+                }
+                self.env.locals.extend(bound);
+            }
+            Op::Test { pattern, fail } => {
+                let value = self.stack.last().expect("value should be on the stack");
+                let pattern = &frame.closure.proto.chunk.patterns[pattern as usize];
+                let mut bound = Vec::new();
+                if pattern.destructure(value, &mut bound) {
+                    self.env.locals.extend(bound);
+                } else {
+                    frame.ip = fail as usize;
+                }
+            }
+            Op::Truncate(n) => self.env.locals.truncate(frame.base + n as usize),
+
+            Op::Closure(i) => {
+                let proto = frame.closure.proto.chunk.protos[i as usize].clone();
+                let upvalues = proto
+                    .upvalues
+                    .iter()
+                    .map(|location| match location {
+                        IdentLocation::Stack(i) => self.env.locals[frame.base + i.0].clone(),
+                        IdentLocation::Upvalue(i) => frame.closure.upvalues[i.0].clone(),
+                    })
+                    .collect();
+                let closure = Closure { proto, upvalues };
+                self.stack
+                    .push(Value::Func(Func::Compiled(Rc::new(closure))));
+            }
+            Op::Call { args, spans } => self.call(frame, args, spans, false)?,
+            Op::TailCall { args, spans } => self.call(frame, args, spans, true)?,
+            Op::Return => {
+                let value = self.pop();
+                let Some(caller) = self.frames.pop() else {
+                    return Ok(Some(value));
+                };
+                self.stack.truncate(frame.stack_base);
+                self.env.locals.truncate(frame.base);
+                *frame = caller;
+                self.stack.push(value);
+            }
+
+            Op::Jump(target) => frame.ip = target as usize,
+            Op::JumpIfFalse(target) => {
+                if !self.pop().is_truthy() {
+                    frame.ip = target as usize;
+                }
+            }
+            Op::JumpIfTrueOrPop(target) => {
+                if self.peek().is_truthy() {
+                    frame.ip = target as usize;
+                } else {
+                    self.pop();
+                }
+            }
+            Op::JumpIfFalseOrPop(target) => {
+                if !self.peek().is_truthy() {
+                    frame.ip = target as usize;
+                } else {
+                    self.pop();
+                }
+            }
+
+            Op::Binary(op, spans) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                let value = binary(op, lhs, rhs, frame.span(spans), frame.span(spans + 1))?;
+                self.stack.push(value);
+            }
+            Op::Not => {
+                let value = self.pop();
+                self.stack.push(Value::Bool(!value.is_truthy()));
+            }
+            Op::Negate(span) => {
+                let value = self.pop();
+                let num = value.as_num().map_err(|e| e.span(frame.span(span)))?;
+                self.stack.push(Value::Num(-num));
+            }
+
+            Op::Interpolate(n) => {
+                let start = self.stack.len() - n as usize;
+                // Same formatting as `print`
+                let s: String = self
+                    .stack
+                    .drain(start..)
+                    .map(|part| part.to_string())
+                    .collect();
+                self.stack.push(Value::Str(s.into()));
+            }
+            Op::List(n) => {
+                let start = self.stack.len() - n as usize;
+                let items = self.stack.drain(start..).collect();
+                self.stack.push(Value::List(items));
+            }
+            Op::Index(spans) => {
+                let index = self.pop();
+                let list = self.pop();
+                let list = list.as_list().map_err(|e| e.span(frame.span(spans)))?;
+                let index = index.as_num().map_err(|e| e.span(frame.span(spans + 1)))?;
+                let item = get_index(&list, index).map_err(|e| e.span(frame.span(spans + 2)))?;
+                self.stack.push(item);
+            }
+            Op::Record(i) => {
+                let shape = &frame.closure.proto.chunk.records[i as usize];
+                let start = self.stack.len() - shape.fields.len();
+                let values: Vec<_> = self.stack.drain(start..).collect();
+                let mut record = match shape.base {
+                    Some(span) => self.pop().into_record().map_err(|e| e.span(span))?,
+                    None => Rc::default(),
+                };
+                for ((name, span), value) in shape.fields.iter().zip(values) {
+                    // Updates can't add new fields
+                    if shape.base.is_some() && !record.contains_key(name) {
+                        return Err(Error::new(ErrorKind::NoSuchField {
+                            field: name.clone(),
+                        })
+                        .span(*span));
+                    }
+                    // Only copies the fields if the base is used elsewhere
+                    Rc::make_mut(&mut record).insert(name.clone(), value);
+                }
+                self.stack.push(Value::Record(record));
+            }
+            Op::Field { name, spans } => {
+                let record = self.pop();
+                let record = record.as_record().map_err(|e| e.span(frame.span(spans)))?;
+                let name = &frame.closure.proto.chunk.names[name as usize];
+                let value = record.get(name).cloned().ok_or_else(|| {
+                    Error::new(ErrorKind::NoSuchField {
+                        field: name.clone(),
+                    })
+                    .span(frame.span(spans + 1))
+                })?;
+                self.stack.push(value);
+            }
+            Op::NoMatch(span) => {
+                return Err(Error::new(ErrorKind::NoMatchingArm {
+                    value: self.peek().to_string(),
+                })
+                .span(frame.span(span)));
+            }
+            Op::Import { module, span } => {
+                let module = frame.closure.proto.chunk.modules[module as usize].clone();
+                let exports = self.import(&module, frame.span(span))?;
+                self.stack.push(exports);
+            }
+        }
+        Ok(None)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("value should be on the stack")
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("value should be on the stack")
+    }
+
+    /// Calls the function under the arguments. A compiled function gets a
+    /// new frame (or replaces the current one, for a tail call), and native
+    /// functions are called right away.
+    fn call(&mut self, frame: &mut Frame, args: u32, spans: u32, is_tail_call: bool) -> Result<()> {
+        let (target_span, call_span) = (frame.span(spans), frame.span(spans + 1));
+
+        let start = self.stack.len() - args as usize;
+        let arguments: Vec<Value> = self.stack.drain(start..).collect();
+        let target = self.pop();
+        let Value::Func(func) = target else {
+            return Err(Error::new(ErrorKind::TypeError {
+                expected: DiagnosticType::Func,
+                actual: target.into(),
+            })
+            .span(target_span));
+        };
+
+        let closure = match func {
+            Func::Compiled(closure) => closure,
+            Func::Native(func) => {
+                let value = func
+                    .call(&mut Ctx::vm(self.env), arguments)
+                    .map_err(|e| e.span(call_span))?;
+                self.stack.push(value);
+                return Ok(());
+            }
+            Func::Constructor(info) => {
+                let value = construct(&info, arguments).map_err(|e| e.span(call_span))?;
+                self.stack.push(value);
+                return Ok(());
+            }
+            func @ Func::User(_) => {
+                return Err(Error::new(ErrorKind::ForeignFunc {
+                    func: Value::Func(func).to_string(),
+                })
+                .span(call_span))
+            }
+        };
+
+        Arity::exact(closure.proto.arity)
+            .check(arguments.len())
+            .map_err(|e| e.span(call_span))?;
+
+        if is_tail_call {
+            self.env.locals.truncate(frame.base);
+            self.stack.truncate(frame.stack_base);
+            frame.closure = closure.clone();
+            frame.ip = 0;
+            frame.tail_calls += 1;
+        } else {
+            let callee = Frame {
+                closure: closure.clone(),
+                ip: 0,
+                base: self.env.locals.len(),
+                stack_base: self.stack.len(),
+                call_site: Some(call_span),
+                tail_calls: 0,
+            };
+            self.frames.push(std::mem::replace(frame, callee));
+        }
+
+        // Slot 0 is the function itself, to allow for recursion
+        self.env.locals.push(Value::Func(Func::Compiled(closure)));
+        self.env.locals.extend(arguments);
+        Ok(())
+    }
+
+    /// The exports of `module`, running it in its own env if it hasn't been
+    /// already.
+    fn import(&mut self, module: &Module, span: Span) -> Result<Value> {
+        if let Some(exports) = self.env.module_exports(&module.path) {
+            return Ok(exports.clone());
+        }
+
+        let mut module_env = self.env.module_env();
+        let proto = compiler::compile_module(module, module_env.locals.len());
+        let exports = Vm::new(&mut module_env).execute(proto);
+        self.env.end_module_env(module_env);
+
+        let exports = exports.map_err(|error| {
+            Error::new(ErrorKind::InModule {
+                name: module.name.clone(),
+                source: module.source.clone(),
+                error: Box::new(error),
+            })
+            .span(span)
+        })?;
+        self.env.cache_module(module.path.clone(), exports.clone());
+        Ok(exports)
+    }
+}
+
+fn binary(op: BinaryOp, lhs: Value, rhs: Value, lhs_span: Span, rhs_span: Span) -> Result<Value> {
+    use Value::{Bool, Num};
+
+    let num = |value: &Value, span: Span| value.as_num().map_err(|e| e.span(span));
+    Ok(match op {
+        BinaryOp::NotEq => Bool(lhs != rhs),
+        BinaryOp::Eq => Bool(lhs == rhs),
+        BinaryOp::Greater => Bool(num(&lhs, lhs_span)? > num(&rhs, rhs_span)?),
+        BinaryOp::GreaterEq => Bool(num(&lhs, lhs_span)? >= num(&rhs, rhs_span)?),
+        BinaryOp::Less => Bool(num(&lhs, lhs_span)? < num(&rhs, rhs_span)?),
+        BinaryOp::LessEq => Bool(num(&lhs, lhs_span)? <= num(&rhs, rhs_span)?),
+        BinaryOp::Subtract => Num(num(&lhs, lhs_span)? - num(&rhs, rhs_span)?),
+        BinaryOp::Add => add(lhs, rhs, lhs_span, rhs_span)?,
+        BinaryOp::Divide => Num(num(&lhs, lhs_span)? / num(&rhs, rhs_span)?),
+        BinaryOp::Multiply => Num(num(&lhs, lhs_span)? * num(&rhs, rhs_span)?),
+        BinaryOp::Or | BinaryOp::And => unreachable!("`or` and `and` are compiled to jumps"),
+    })
+}
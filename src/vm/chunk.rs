@@ -0,0 +1,129 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{BinaryOp, IdentLocation, Module, Pattern},
+    interperter::Value,
+    lexer::Span,
+};
+
+/// A single instruction. Anything that doesn't fit in a `u32` is stored in
+/// the chunk, and referred to by index.
+///
+/// Ops that can fail refer to the spans of what they are made of, which the
+/// compiler puts next to each other in `Chunk::spans`.
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// Pushes `constants[i]`.
+    Const(u32),
+    Nil,
+    Pop,
+    /// Pops the value under the top of the stack.
+    PopUnder,
+
+    /// Pushes a local, relative to the start of the frame.
+    GetLocal(u32),
+    GetUpvalue(u32),
+    /// Pops a value, and defines it as the next local.
+    Define,
+    /// Pops a value, and defines the names that `patterns[i]` binds in it.
+    Destructure(u32),
+    /// Like `Destructure`, but leaves the value on the stack, and jumps to
+    /// `fail` instead of erroring if it doesn't match.
+    Test {
+        pattern: u32,
+        fail: u32,
+    },
+    /// Undefines locals until there are only `n` left in the frame.
+    Truncate(u32),
+
+    /// Pushes a closure of `protos[i]`, capturing its upvalues.
+    Closure(u32),
+    /// Calls the function under `args` arguments. Spans: the function, then
+    /// the whole call.
+    Call {
+        args: u32,
+        spans: u32,
+    },
+    /// Like `Call`, but replaces the current frame.
+    TailCall {
+        args: u32,
+        spans: u32,
+    },
+    Return,
+
+    Jump(u32),
+    /// Pops the condition.
+    JumpIfFalse(u32),
+    /// Only pops the condition if it doesn't jump, for `or`.
+    JumpIfTrueOrPop(u32),
+    /// Only pops the condition if it doesn't jump, for `and`.
+    JumpIfFalseOrPop(u32),
+
+    /// Any operator but `or` and `and`. Spans: the lhs, then the rhs.
+    Binary(BinaryOp, u32),
+    Not,
+    /// Spans: the operand.
+    Negate(u32),
+
+    /// Concatenates the top `n` values, converted to strings.
+    Interpolate(u32),
+    /// Makes a list of the top `n` values.
+    List(u32),
+    /// Spans: the list, the index, and then the whole expression.
+    Index(u32),
+    /// Makes a record shaped like `records[i]`, from its base (if any) and
+    /// then each field.
+    Record(u32),
+    /// Gets the field `names[name]`. Spans: the record, then the whole
+    /// expression.
+    Field {
+        name: u32,
+        spans: u32,
+    },
+    /// Errors that no arm matched the value on the stack. Spans: the value.
+    NoMatch(u32),
+    /// Pushes the exports of `modules[module]`, running it if it hasn't been
+    /// already.
+    Import {
+        module: u32,
+        span: u32,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Value>,
+    pub spans: Vec<Span>,
+    pub names: Vec<String>,
+    pub patterns: Vec<Pattern>,
+    pub protos: Vec<Rc<Proto>>,
+    pub records: Vec<RecordShape>,
+    pub modules: Vec<Rc<Module>>,
+}
+
+/// The fields a record expression sets, and whether it updates a base.
+#[derive(Debug)]
+pub struct RecordShape {
+    /// The span of the base, if there is one.
+    pub base: Option<Span>,
+    pub fields: Vec<(String, Span)>,
+}
+
+/// A compiled function. Each time its binding is run, a new closure of it is
+/// made.
+#[derive(Debug)]
+pub struct Proto {
+    pub name: String,
+    pub arity: usize,
+    /// Where each upvalue is captured from, in the frame the closure is made
+    /// in.
+    pub upvalues: Vec<IdentLocation>,
+    pub chunk: Chunk,
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub proto: Rc<Proto>,
+    pub upvalues: Vec<Value>,
+}
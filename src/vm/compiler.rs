@@ -0,0 +1,403 @@
+use std::rc::Rc;
+
+use super::chunk::{Chunk, Op, Proto, RecordShape};
+use crate::{
+    ast::{
+        BinaryOp, Binding, BindingMetadata, Block, Call, ElseBlock, Expr, IdentLocation, IfExpr,
+        Import, Match, Module, Pattern, Program, Record, Stmt, TypeDecl, UnaryOp,
+    },
+    interperter::{Func, Value, Variant},
+    lexer::Span,
+};
+
+/// Compiles a program run at the top level, after `num_locals` locals have
+/// already been defined (by the stdlib and anything run before). It returns
+/// the value of its last statement if that is an expression.
+pub fn compile_program(program: &Program, num_locals: usize) -> Rc<Proto> {
+    let mut compiler = Compiler::new(num_locals);
+    match program.split_last() {
+        Some((Stmt::Expr(expr), stmts)) => {
+            compiler.stmts(stmts);
+            compiler.expr(expr);
+        }
+        _ => {
+            compiler.stmts(program);
+            compiler.emit(Op::Nil);
+        }
+    }
+    compiler.finish("<main>".to_string(), 0, Vec::new())
+}
+
+/// Compiles a module, which returns its exports.
+pub fn compile_module(module: &Module, num_locals: usize) -> Rc<Proto> {
+    let mut compiler = Compiler::new(num_locals);
+    compiler.stmts(&module.program);
+    compiler.record(&module.exports);
+    compiler.finish(format!("<module {}>", module.name), 0, Vec::new())
+}
+
+struct Compiler {
+    chunk: Chunk,
+    /// How many locals are defined in the frame at this point, which is
+    /// known without running anything.
+    num_locals: usize,
+}
+
+impl Compiler {
+    fn new(num_locals: usize) -> Self {
+        Compiler {
+            chunk: Chunk::default(),
+            num_locals,
+        }
+    }
+
+    fn finish(mut self, name: String, arity: usize, upvalues: Vec<IdentLocation>) -> Rc<Proto> {
+        self.emit(Op::Return);
+        Rc::new(Proto {
+            name,
+            arity,
+            upvalues,
+            chunk: self.chunk,
+        })
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    /// Where the next op will be.
+    fn here(&self) -> u32 {
+        self.chunk.code.len() as u32
+    }
+
+    /// Makes the jump at `jump` go to the next op.
+    fn patch_jump(&mut self, jump: usize) {
+        let here = self.here();
+        match &mut self.chunk.code[jump] {
+            Op::Jump(target)
+            | Op::JumpIfFalse(target)
+            | Op::JumpIfTrueOrPop(target)
+            | Op::JumpIfFalseOrPop(target)
+            | Op::Test { fail: target, .. } => *target = here,
+            op => unreachable!("{op:?} should be a jump"),
+        }
+    }
+
+    /// Adds the spans, in order, and returns the index of the first.
+    fn spans<const N: usize>(&mut self, spans: [Span; N]) -> u32 {
+        let index = self.chunk.spans.len() as u32;
+        self.chunk.spans.extend(spans);
+        index
+    }
+
+    fn constant(&mut self, value: Value) {
+        self.chunk.constants.push(value);
+        let index = self.chunk.constants.len() as u32 - 1;
+        self.emit(Op::Const(index));
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> u32 {
+        self.chunk.patterns.push(pattern.clone());
+        self.chunk.patterns.len() as u32 - 1
+    }
+
+    fn define(&mut self) {
+        self.emit(Op::Define);
+        self.num_locals += 1;
+    }
+
+    /// Pops a value and binds it to `pattern`.
+    fn destructure(&mut self, pattern: &Pattern) {
+        if let Pattern::Identifier(_) = pattern {
+            self.define();
+            return;
+        }
+        let pattern_index = self.pattern(pattern);
+        self.emit(Op::Destructure(pattern_index));
+        self.num_locals += count_bindings(pattern);
+    }
+
+    /// Undefines the locals defined since there were `num_locals`.
+    fn truncate(&mut self, num_locals: usize) {
+        if self.num_locals != num_locals {
+            self.emit(Op::Truncate(num_locals as u32));
+            self.num_locals = num_locals;
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let(binding) => self.binding(binding),
+            Stmt::Type(type_decl) => self.type_decl(type_decl),
+            Stmt::Import(import) => self.import(import),
+            Stmt::Expr(expr) => {
+                self.expr(expr);
+                self.emit(Op::Pop);
+            }
+        }
+    }
+
+    fn binding(&mut self, binding: &Binding) {
+        match &binding.metadata {
+            BindingMetadata::Func {
+                arguments,
+                upvalues,
+                ..
+            } => {
+                let Pattern::Identifier(ident) = &binding.pattern else {
+                    unreachable!("parser should only make named functions");
+                };
+                let upvalues = upvalues.iter().map(|upvalue| upvalue.target).collect();
+                let proto = compile_func(ident.name.clone(), arguments, upvalues, &binding.value);
+                self.chunk.protos.push(proto);
+                let index = self.chunk.protos.len() as u32 - 1;
+                self.emit(Op::Closure(index));
+            }
+            BindingMetadata::Var => self.expr(&binding.value),
+        }
+        self.destructure(&binding.pattern);
+    }
+
+    fn type_decl(&mut self, type_decl: &TypeDecl) {
+        // Defined in the same order that the parser declared them
+        for variant in &type_decl.variants {
+            let info = &variant.info;
+            self.constant(match info.arity {
+                Some(_) => Value::Func(Func::Constructor(info.clone())),
+                None => Value::Variant(Variant {
+                    type_name: info.type_name.clone(),
+                    name: info.name.clone(),
+                    fields: Vec::new(),
+                }),
+            });
+            self.define();
+        }
+    }
+
+    fn import(&mut self, import: &Import) {
+        self.chunk.modules.push(import.module.clone());
+        let module = self.chunk.modules.len() as u32 - 1;
+        let span = self.spans([import.span]);
+        self.emit(Op::Import { module, span });
+        self.destructure(&import.pattern);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Block(block) => self.block(block),
+            Expr::Call(call) => self.call(call),
+            Expr::If(if_expr) => self.if_expr(if_expr),
+            Expr::Match(match_expr) => self.match_expr(match_expr),
+            Expr::Binary(binary_expr) => {
+                self.expr(&binary_expr.lhs);
+                let jump = match binary_expr.op {
+                    BinaryOp::Or => Op::JumpIfTrueOrPop(0),
+                    BinaryOp::And => Op::JumpIfFalseOrPop(0),
+                    op => {
+                        self.expr(&binary_expr.rhs);
+                        let spans = self.spans([binary_expr.lhs.span(), binary_expr.rhs.span()]);
+                        self.emit(Op::Binary(op, spans));
+                        return;
+                    }
+                };
+                // Short-circuit
+                let jump = self.emit(jump);
+                self.expr(&binary_expr.rhs);
+                self.patch_jump(jump);
+            }
+            Expr::Unary(unary_expr) => {
+                self.expr(&unary_expr.rhs);
+                match unary_expr.op {
+                    UnaryOp::Not => self.emit(Op::Not),
+                    UnaryOp::Negate => {
+                        let spans = self.spans([unary_expr.rhs.span()]);
+                        self.emit(Op::Negate(spans))
+                    }
+                };
+            }
+            Expr::Literal(literal) => self.constant(Value::from(&literal.value)),
+            Expr::Interpolated(interpolated) => {
+                for part in &interpolated.parts {
+                    self.expr(part);
+                }
+                self.emit(Op::Interpolate(interpolated.parts.len() as u32));
+            }
+            Expr::List(list) => {
+                for item in &list.items {
+                    self.expr(item);
+                }
+                self.emit(Op::List(list.items.len() as u32));
+            }
+            Expr::Index(index) => {
+                self.expr(&index.target);
+                self.expr(&index.index);
+                let spans = self.spans([index.target.span(), index.index.span(), index.span]);
+                self.emit(Op::Index(spans));
+            }
+            Expr::Record(record) => self.record(record),
+            Expr::Field(field_access) => {
+                self.expr(&field_access.target);
+                self.chunk.names.push(field_access.field.clone());
+                let name = self.chunk.names.len() as u32 - 1;
+                let spans = self.spans([field_access.target.span(), field_access.span]);
+                self.emit(Op::Field { name, spans });
+            }
+            Expr::Identifier(identifier) => {
+                self.emit(
+                    match identifier
+                        .location
+                        .expect("parser should have resolved variable")
+                    {
+                        IdentLocation::Stack(i) => Op::GetLocal(i.0 as u32),
+                        IdentLocation::Upvalue(i) => Op::GetUpvalue(i.0 as u32),
+                    },
+                );
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        let num_locals = self.num_locals;
+        self.stmts(&block.stmts);
+        match &block.return_expr {
+            Some(return_expr) => self.expr(return_expr),
+            None => {
+                self.emit(Op::Nil);
+            }
+        }
+        self.truncate(num_locals);
+    }
+
+    fn call(&mut self, call: &Call) {
+        self.expr(&call.target);
+        for argument in &call.arguments {
+            self.expr(argument);
+        }
+        let args = call.arguments.len() as u32;
+        let spans = self.spans([call.target.span(), call.span]);
+        self.emit(if call.is_tail_call {
+            Op::TailCall { args, spans }
+        } else {
+            Op::Call { args, spans }
+        });
+    }
+
+    fn if_expr(&mut self, if_expr: &IfExpr) {
+        self.expr(&if_expr.condition);
+        let to_else = self.emit(Op::JumpIfFalse(0));
+        self.block(&if_expr.then_block);
+        let to_end = self.emit(Op::Jump(0));
+
+        self.patch_jump(to_else);
+        match &if_expr.else_block {
+            Some(ElseBlock::Else(else_block)) => self.block(else_block),
+            Some(ElseBlock::ElseIf(if_expr)) => self.if_expr(if_expr),
+            None => {
+                self.emit(Op::Nil);
+            }
+        }
+        self.patch_jump(to_end);
+    }
+
+    /// The value stays on the stack while the arms are tried, and is popped
+    /// from under the result of the one that matches.
+    fn match_expr(&mut self, match_expr: &Match) {
+        self.expr(&match_expr.value);
+
+        let mut to_end = Vec::new();
+        for arm in &match_expr.arms {
+            let num_locals = self.num_locals;
+            let pattern = self.pattern(&arm.pattern);
+            let to_next_arm = self.emit(Op::Test { pattern, fail: 0 });
+            self.num_locals += count_bindings(&arm.pattern);
+            let num_bound = self.num_locals;
+
+            let guard_failed = arm.guard.as_ref().map(|guard| {
+                self.expr(guard);
+                self.emit(Op::JumpIfFalse(0))
+            });
+
+            self.expr(&arm.body);
+            self.truncate(num_locals);
+            self.emit(Op::PopUnder);
+            to_end.push(self.emit(Op::Jump(0)));
+
+            if let Some(guard_failed) = guard_failed {
+                self.patch_jump(guard_failed);
+                self.num_locals = num_bound;
+                self.truncate(num_locals);
+            }
+            self.patch_jump(to_next_arm);
+        }
+
+        let spans = self.spans([match_expr.value.span()]);
+        self.emit(Op::NoMatch(spans));
+        for jump in to_end {
+            self.patch_jump(jump);
+        }
+    }
+
+    fn record(&mut self, record: &Record) {
+        if let Some(base) = &record.base {
+            self.expr(base);
+        }
+        for field in &record.fields {
+            self.expr(&field.value);
+        }
+        self.chunk.records.push(RecordShape {
+            base: record.base.as_ref().map(|base| base.span()),
+            fields: record
+                .fields
+                .iter()
+                .map(|field| (field.name.clone(), field.span))
+                .collect(),
+        });
+        let index = self.chunk.records.len() as u32 - 1;
+        self.emit(Op::Record(index));
+    }
+}
+
+/// The function itself is in slot 0, and each argument is in its own slot
+/// after it. Names bound by destructuring arguments come after all of them.
+fn compile_func(
+    name: String,
+    arguments: &[Pattern],
+    upvalues: Vec<IdentLocation>,
+    body: &Expr,
+) -> Rc<Proto> {
+    let mut compiler = Compiler::new(1 + arguments.len());
+    for (i, pattern) in arguments.iter().enumerate() {
+        if matches!(pattern, Pattern::Identifier(_)) {
+            continue;
+        }
+        compiler.emit(Op::GetLocal(i as u32 + 1));
+        compiler.destructure(pattern);
+    }
+    compiler.expr(body);
+    compiler.finish(name, arguments.len(), upvalues)
+}
+
+/// How many names `pattern` binds.
+fn count_bindings(pattern: &Pattern) -> usize {
+    match pattern {
+        Pattern::Identifier(_) => 1,
+        Pattern::Wildcard(_) | Pattern::Literal(_) => 0,
+        Pattern::List(list) => {
+            list.items.iter().map(count_bindings).sum::<usize>()
+                + list.rest.as_deref().map_or(0, count_bindings)
+        }
+        Pattern::Record(record) => record
+            .fields
+            .iter()
+            .map(|field| count_bindings(&field.pattern))
+            .sum(),
+        Pattern::Variant(variant) => variant.fields.iter().map(count_bindings).sum(),
+    }
+}
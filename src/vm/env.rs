@@ -0,0 +1,77 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::StackIndex,
+    interperter::{native_funcs, Value},
+};
+
+/// What the VM keeps between runs, like in a REPL.
+#[derive(Clone)]
+pub struct Env {
+    /// Every frame's locals, one after another. The top-level locals are
+    /// first.
+    pub(super) locals: Vec<Value>,
+    /// The exports of every module that has been run, by canonical path.
+    modules: HashMap<PathBuf, Value>,
+    /// Functions the host registered, which modules can call too.
+    host: Vec<Value>,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Env {
+            locals: native_funcs(),
+            modules: HashMap::new(),
+            host: Vec::new(),
+        }
+    }
+
+    /// Defines a top-level local.
+    pub fn define(&mut self, value: Value) {
+        self.locals.push(value);
+    }
+
+    /// Defines a function the host registered, which imported modules can
+    /// call too.
+    pub fn define_host(&mut self, value: Value) {
+        self.define(value.clone());
+        self.host.push(value);
+    }
+
+    /// A top-level local.
+    pub fn get(&self, StackIndex(i): StackIndex) -> &Value {
+        &self.locals[i]
+    }
+
+    pub fn module_exports(&self, path: &Path) -> Option<&Value> {
+        self.modules.get(path)
+    }
+
+    /// A fresh env to run a module in. It shares the modules that have
+    /// already been run, and must be given back with `end_module_env`.
+    pub fn module_env(&mut self) -> Env {
+        let mut env = Env::new();
+        for value in &self.host {
+            env.define_host(value.clone());
+        }
+        env.modules = std::mem::take(&mut self.modules);
+        env
+    }
+
+    pub fn end_module_env(&mut self, module_env: Env) {
+        self.modules = module_env.modules;
+    }
+
+    pub fn cache_module(&mut self, path: PathBuf, exports: Value) {
+        self.modules.insert(path, exports);
+    }
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,7 +1,10 @@
+use std::{collections::HashMap, path::PathBuf, rc::Rc};
+
 use wasm::binary::{IntoBytes, WasmVec};
 
 use crate::ast;
 
+mod unboxed;
 mod wasm;
 
 // Whether or not to generate code to check types of boxes at runtime.
@@ -14,6 +17,24 @@ pub fn gen_wasm(program: ast::Program) -> Vec<u8> {
 struct WasmGenState {
     module: wasm::Module,
     mem_store: MemStore,
+
+    /// `host.to_str(value: ptr, dest: ptr) -> len`. Writes the UTF-8 bytes of
+    /// `value`, formatted the same way as `print`, to `dest`. Returns how many
+    /// bytes were written.
+    host_to_str: wasm::FuncIdx,
+
+    /// Boxed values that are put at the very start of memory when the module
+    /// is instantiated, before anything is allocated.
+    static_data: Vec<u8>,
+    /// The address in `static_data` of each interned name (record fields
+    /// and variants).
+    interned_names: HashMap<String, u32>,
+
+    /// The function that runs each imported module (returning its exports),
+    /// and the global caching its exports once it has been run.
+    modules: HashMap<PathBuf, (wasm::FuncIdx, wasm::GlobalIdx)>,
+    /// How many of the host's imports are exposed as stdlib functions.
+    num_stdlib_imports: usize,
 }
 impl WasmGenState {
     fn gen(program: ast::Program) -> Vec<u8> {
@@ -31,6 +52,21 @@ impl WasmGenState {
             },
         };
         module.funcs.insert_import(import_host_print);
+        // Only these imports are exposed as stdlib functions
+        let num_stdlib_imports = module.funcs.imports().len();
+
+        let import_host_to_str = wasm::FuncImport {
+            module: wasm::Name("host".to_string()),
+            name: wasm::Name("to_str".to_string()),
+            ty: {
+                let ty = wasm::FuncType {
+                    params: [MEM_PTR_TY, MEM_PTR_TY].into_iter().collect(),
+                    results: [wasm::ValType::I32].into_iter().collect(),
+                };
+                module.ty_sec.insert(ty)
+            },
+        };
+        let host_to_str = module.funcs.insert_import(import_host_to_str);
 
         let mut table_sec = wasm::TableSection::new();
         table_sec.insert(wasm::TableType {
@@ -74,33 +110,110 @@ impl WasmGenState {
         let mut state = WasmGenState {
             module,
             mem_store: MemStore::new(mem_idx, global_mem_alloc_ptr),
+            host_to_str,
+            static_data: Vec::new(),
+            interned_names: HashMap::new(),
+            modules: HashMap::new(),
+            num_stdlib_imports,
         };
 
-        // Add imports vars to main func.
+        state.gen_stdlib(&mut main_func);
+        state.gen_program(&mut main_func, program);
+        state.finish(main_func)
+    }
+
+    /// Defines the stdlib functions as the first locals of `func`, the same
+    /// way the parser declares them.
+    fn gen_stdlib(&mut self, func: &mut wasm::Func) {
         // Assumes that import indexes are in order (`.enumerate()`), and that
         // new imports will not be added after/during this loop (`.clone()`).
-        for (i, import) in state.module.funcs.imports().clone().iter().enumerate() {
+        let stdlib_imports = self.module.funcs.imports()[..self.num_stdlib_imports].to_vec();
+        for (i, import) in stdlib_imports.iter().enumerate() {
             // TODO: actually track # of args + result
             let ty = wasm::FuncType::new(1, MEM_PTR_TY);
-            let ty = state.module.ty_sec.insert(ty);
-            let mut func = wasm::Func::new(ty, Some(import.dbg_name()), [None], &[]);
+            let ty = self.module.ty_sec.insert(ty);
+            let mut new_func = wasm::Func::new(ty, Some(import.dbg_name()), [None], &[]);
             // Assumes first arg is at index 1
-            func.gen_stack_get(&ast::IdentLocation::Stack(ast::StackIndex(1)));
-            func.body.extend(wasm::binary::CALL);
-            func.body.extend(i as u32);
-            state.gen_boxed_nil(&mut func);
+            new_func.gen_stack_get(&ast::IdentLocation::Stack(ast::StackIndex(1)));
+            new_func.body.extend(wasm::binary::CALL);
+            new_func.body.extend(i as u32);
+            self.gen_boxed_nil(&mut new_func);
 
-            state.gen_func_def(&mut main_func, func, [], Some(import.dbg_name()));
-            main_func.gen_local_set(
+            self.gen_func_def(func, new_func, [], Some(import.dbg_name()));
+            func.gen_local_set(
                 MEM_PTR_TY,
                 // Assumes that the imports are in order in the ast stack
                 Some(ast::IdentLocation::Stack(ast::StackIndex(i))),
                 Some(import.dbg_name()),
             );
         }
+    }
 
-        state.gen_program(&mut main_func, program);
-        state.finish(main_func)
+    /// The function that runs `module` and returns its exports, and the
+    /// global they are cached in. Each module is only generated once.
+    fn gen_module(&mut self, module: &ast::Module) -> (wasm::FuncIdx, wasm::GlobalIdx) {
+        if let Some(module) = self.modules.get(&module.path) {
+            return *module;
+        }
+
+        let dbg_name = wasm::Name(format!("<module {}>", module.name));
+        let ty = self.module.ty_sec.insert(wasm::FuncType {
+            params: WasmVec::new(),
+            results: [MEM_PTR_TY].into_iter().collect(),
+        });
+        // Modules run in an env of their own, like the main program
+        let mut func = wasm::Func::new_base(ty, []);
+        self.gen_stdlib(&mut func);
+        self.gen_program(&mut func, module.program.clone());
+        self.gen_record_expr(module.exports.clone(), &mut func);
+        let func_idx =
+            self.module
+                .funcs
+                .insert(func, &mut self.module.name_sec, Some(dbg_name.clone()));
+
+        // -1 means the module hasn't been run yet
+        let mut init = wasm::binary::Expr::new();
+        init.extend(wasm::binary::CONST_I32);
+        init.extend(-1);
+        let global_idx = self.module.globals_sec.insert(
+            wasm::Global {
+                ty: MEM_PTR_TY,
+                mutable: true,
+                init,
+            },
+            Some((&mut self.module.name_sec, dbg_name)),
+        );
+
+        self.modules
+            .insert(module.path.clone(), (func_idx, global_idx));
+        (func_idx, global_idx)
+    }
+
+    /// Runs the module (unless it already has been), and binds its exports.
+    fn gen_import(&mut self, func: &mut wasm::Func, import: ast::Import) {
+        use wasm::binary::{CALL, CONST_I32, END, EQ_I32, GLOBAL_GET, GLOBAL_SET, IF, TY_NEVER};
+
+        let (func_idx, global_idx) = self.gen_module(&import.module);
+        func.body.extend(GLOBAL_GET);
+        func.body.extend(global_idx);
+        func.body.extend(CONST_I32);
+        func.body.extend(-1);
+        func.body.extend([EQ_I32, IF, TY_NEVER]);
+        func.body.extend(CALL);
+        func.body.extend(func_idx);
+        func.body.extend(GLOBAL_SET);
+        func.body.extend(global_idx);
+        func.body.extend(END);
+        func.body.extend(GLOBAL_GET);
+        func.body.extend(global_idx);
+
+        match import.pattern {
+            ast::Pattern::Identifier(ident) => Self::gen_ident_set(func, &ident),
+            pattern => {
+                let value = func.gen_local_set(MEM_PTR_TY, None, None);
+                self.gen_destructure(func, &pattern, value);
+            }
+        }
     }
 
     fn finish(mut self, func: wasm::Func) -> Vec<u8> {
@@ -131,6 +244,23 @@ impl WasmGenState {
         elem_sec.insert(elem_segment);
         self.module.elem_sec = Some(elem_sec);
 
+        // Put the static data at the start of memory, and only allocate after
+        // it
+        let static_len = self.static_data.len() as i32;
+        let mut data_sec = wasm::DataSection::new();
+        data_sec.insert(wasm::Data {
+            offset: 0,
+            bytes: self.static_data.into_iter().collect(),
+        });
+        self.module.data_sec = Some(data_sec);
+        let mem_alloc_ptr = self
+            .module
+            .globals_sec
+            .get_mut(self.mem_store.global_mem_alloc_ptr);
+        mem_alloc_ptr.init = wasm::binary::Expr::new();
+        mem_alloc_ptr.init.extend(wasm::binary::CONST_I32);
+        mem_alloc_ptr.init.extend(static_len);
+
         self.module.into_bytes()
     }
 
@@ -143,6 +273,8 @@ impl WasmGenState {
     fn gen_stmt(&mut self, func: &mut wasm::Func, stmt: ast::Stmt) {
         match stmt {
             ast::Stmt::Let(binding) => self.gen_binding(func, binding),
+            ast::Stmt::Type(type_decl) => self.gen_type_decl(func, type_decl),
+            ast::Stmt::Import(import) => self.gen_import(func, import),
             ast::Stmt::Expr(expr) => {
                 self.gen_expr(func, expr);
 
@@ -153,42 +285,458 @@ impl WasmGenState {
         }
     }
 
+    /// Binds each variant to either its value (if it has no fields) or its
+    /// constructor.
+    fn gen_type_decl(&mut self, func: &mut wasm::Func, type_decl: ast::TypeDecl) {
+        for variant in type_decl.variants {
+            let name = self.intern_name(&variant.info.name);
+            match variant.info.arity {
+                None => self.gen_variant_box(func, name, &[]),
+                Some(arity) => {
+                    let dbg_name = Some(wasm::Name(variant.info.name.clone()));
+                    let ty = wasm::FuncType::new(arity, MEM_PTR_TY);
+                    let ty = self.module.ty_sec.insert(ty);
+                    let mut new_func = wasm::Func::new(
+                        ty,
+                        dbg_name.clone(),
+                        std::iter::repeat_n(None, arity),
+                        &[],
+                    );
+
+                    // Slot 0 is the function itself
+                    let fields = (0..arity)
+                        .map(|i| {
+                            new_func
+                                .gen_stack_get(&ast::IdentLocation::Stack(ast::StackIndex(i + 1)));
+                            new_func.gen_local_set(MEM_PTR_TY, None, None)
+                        })
+                        .collect::<Vec<_>>();
+                    self.gen_variant_box(&mut new_func, name, &fields);
+
+                    self.gen_func_def(func, new_func, Vec::new(), dbg_name);
+                }
+            }
+            Self::gen_ident_set(func, &variant.ident);
+        }
+    }
+
+    /// `[] -> [MEM_PTR_TY]`
+    fn gen_variant_box(&mut self, func: &mut wasm::Func, name: u32, fields: &[wasm::LocalIdx]) {
+        let len = fields.len() as u32;
+        let ptr = self
+            .mem_store
+            .alloc_n(func, wasm::BoxType::Variant, 2 + len);
+        func.gen_box(
+            ptr,
+            (0..2 + len)
+                .map(|i| {
+                    move |func: &mut wasm::Func| match i {
+                        0 => {
+                            func.body.extend(wasm::binary::CONST_I32);
+                            func.body.extend(name as i32);
+                        }
+                        1 => {
+                            func.body.extend(wasm::binary::CONST_I32);
+                            func.body.extend(len as i32);
+                        }
+                        i => func.gen_local_get(fields[i as usize - 2]),
+                    }
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+
     fn gen_binding(&mut self, func: &mut wasm::Func, binding: ast::Binding) {
-        let dbg_name = Some(wasm::Name(binding.ident.name.clone()));
         match binding.metadata {
             ast::BindingMetadata::Var => {
-                self.gen_expr(func, binding.value);
+                self.gen_expr(func, Rc::unwrap_or_clone(binding.value));
+                match binding.pattern {
+                    ast::Pattern::Identifier(ident) => Self::gen_ident_set(func, &ident),
+                    pattern => {
+                        let value = func.gen_local_set(MEM_PTR_TY, None, None);
+                        self.gen_destructure(func, &pattern, value);
+                    }
+                }
             }
             ast::BindingMetadata::Func {
                 arguments,
                 upvalues,
+                ..
             } => {
+                let ast::Pattern::Identifier(ident) = binding.pattern else {
+                    unreachable!("parser should only make named functions");
+                };
+                let dbg_name = Some(wasm::Name(ident.name.clone()));
+
                 let ty = wasm::FuncType::new(arguments.len(), MEM_PTR_TY);
                 let ty = self.module.ty_sec.insert(ty);
-                let mut new_func = wasm::Func::new(
-                    ty,
-                    dbg_name.clone(),
-                    arguments.into_iter().map(Some),
-                    upvalues.as_slice(),
-                );
+                let names = arguments.iter().map(|argument| match argument {
+                    ast::Pattern::Identifier(ident) => Some(ident.clone()),
+                    _ => None,
+                });
+                let mut new_func =
+                    wasm::Func::new(ty, dbg_name.clone(), names, upvalues.as_slice());
+
+                // Destructure the arguments that aren't just names
+                for (i, argument) in arguments.iter().enumerate() {
+                    if matches!(argument, ast::Pattern::Identifier(_)) {
+                        continue;
+                    }
+                    // Slot 0 is the function itself
+                    new_func.gen_stack_get(&ast::IdentLocation::Stack(ast::StackIndex(i + 1)));
+                    let value = new_func.gen_local_set(MEM_PTR_TY, None, None);
+                    self.gen_destructure(&mut new_func, argument, value);
+                }
 
-                self.gen_expr(&mut new_func, binding.value);
+                self.gen_expr(&mut new_func, Rc::unwrap_or_clone(binding.value));
 
-                self.gen_func_def(func, new_func, upvalues, dbg_name.clone());
+                self.gen_func_def(func, new_func, upvalues, dbg_name);
+                Self::gen_ident_set(func, &ident);
             }
         }
+    }
 
+    /// Sets the local for `ident` to the top of the stack.
+    ///
+    /// `[MEM_PTR_TY] -> []`
+    fn gen_ident_set(func: &mut wasm::Func, ident: &ast::Identifier) {
         func.gen_local_set(
             MEM_PTR_TY,
             Some(
-                binding.ident.location.unwrap_or_else(|| {
-                    panic!("location resolved for ident, {}", binding.ident.name)
-                }),
+                ident
+                    .location
+                    .unwrap_or_else(|| panic!("location resolved for ident, {}", ident.name)),
             ),
-            dbg_name,
+            Some(wasm::Name(ident.name.clone())),
         );
     }
 
+    /// Binds the names in `pattern`, trapping if `value` doesn't match it.
+    fn gen_destructure(
+        &mut self,
+        func: &mut wasm::Func,
+        pattern: &ast::Pattern,
+        value: wasm::LocalIdx,
+    ) {
+        use wasm::binary::{END, EQZ_I32, IF, TRAP, TY_NEVER};
+
+        self.gen_pattern_test(func, pattern, value);
+        func.body.extend([EQZ_I32, IF, TY_NEVER, TRAP, END]);
+        self.gen_pattern_bind(func, pattern, value);
+    }
+
+    /// Checks whether `value` matches `pattern`, without binding anything.
+    ///
+    /// `[] -> [I32]`
+    fn gen_pattern_test(
+        &mut self,
+        func: &mut wasm::Func,
+        pattern: &ast::Pattern,
+        value: wasm::LocalIdx,
+    ) {
+        use wasm::binary::{
+            CONST_F64, CONST_I32, ELSE, END, EQ_F64, EQ_I32, GE_U_I32, IF, LT_U_I32, MEM_F64_LOAD,
+            MEM_I32_LOAD, MEM_I32_LOAD_8U, TY_I32,
+        };
+
+        // Each check is followed by an `if`, so that the later checks (which
+        // may read memory assuming the earlier ones passed) only run if it
+        // passes.
+        let mut num_checks = 0;
+        let mut then = |func: &mut wasm::Func| {
+            func.body.extend([IF, TY_I32]);
+            num_checks += 1;
+        };
+        let gen_tag_eq = |func: &mut wasm::Func, box_ty: wasm::BoxType| {
+            func.gen_local_get(value);
+            func.gen_load(MEM_I32_LOAD_8U, 0);
+            func.body.extend(CONST_I32);
+            func.body.extend(box_ty.tag());
+            func.body.extend(EQ_I32);
+        };
+
+        match pattern {
+            ast::Pattern::Identifier(_) | ast::Pattern::Wildcard(_) => {}
+            ast::Pattern::Literal(literal) => match &literal.value {
+                ast::LiteralValue::Bool(b) => {
+                    gen_tag_eq(func, wasm::BoxType::Bool);
+                    then(func);
+                    func.gen_local_get(value);
+                    func.gen_load(MEM_I32_LOAD_8U, 1);
+                    func.body.extend(CONST_I32);
+                    func.body.extend(*b);
+                    func.body.extend(EQ_I32);
+                    then(func);
+                }
+                ast::LiteralValue::Number(n) => {
+                    gen_tag_eq(func, wasm::BoxType::Num);
+                    then(func);
+                    func.gen_local_get(value);
+                    func.gen_load(MEM_F64_LOAD, 1);
+                    func.body.extend(CONST_F64);
+                    func.body.extend(*n);
+                    func.body.extend(EQ_F64);
+                    then(func);
+                }
+                ast::LiteralValue::Str(s) => {
+                    gen_tag_eq(func, wasm::BoxType::String);
+                    then(func);
+                    let (len, bytes) = Self::gen_str_len(func, value);
+                    func.gen_local_get(len);
+                    func.body.extend(CONST_I32);
+                    func.body.extend(s.len() as i32);
+                    func.body.extend(EQ_I32);
+                    then(func);
+                    for (i, byte) in s.bytes().enumerate() {
+                        func.gen_local_get(bytes);
+                        func.gen_load(MEM_I32_LOAD_8U, i as u32);
+                        func.body.extend(CONST_I32);
+                        func.body.extend(byte as i32);
+                        func.body.extend(EQ_I32);
+                        then(func);
+                    }
+                }
+                ast::LiteralValue::Nil => {
+                    gen_tag_eq(func, wasm::BoxType::Nil);
+                    then(func);
+                }
+            },
+            ast::Pattern::List(list) => {
+                gen_tag_eq(func, wasm::BoxType::List);
+                then(func);
+
+                func.gen_local_get(value);
+                func.gen_load(MEM_I32_LOAD, 1);
+                func.body.extend(CONST_I32);
+                func.body.extend(list.items.len() as i32);
+                func.body.extend(if list.rest.is_some() {
+                    GE_U_I32
+                } else {
+                    EQ_I32
+                });
+                then(func);
+
+                for (i, item) in list.items.iter().enumerate() {
+                    func.gen_local_get(value);
+                    func.gen_load(MEM_I32_LOAD, Self::list_item_offset(i));
+                    let item_value = func.gen_local_set(MEM_PTR_TY, None, None);
+                    self.gen_pattern_test(func, item, item_value);
+                    then(func);
+                }
+                // The rest is always a name or wildcard, so it always matches
+            }
+            ast::Pattern::Record(record) => {
+                gen_tag_eq(func, wasm::BoxType::Record);
+                then(func);
+
+                func.gen_local_get(value);
+                func.gen_load(MEM_I32_LOAD, 1);
+                let len = func.gen_local_set(wasm::ValType::I32, None, None);
+                for field in &record.fields {
+                    let name = self.intern_name(&field.name);
+                    let i = Self::gen_record_find(func, value, len, name);
+                    func.gen_local_get(i);
+                    func.gen_local_get(len);
+                    func.body.extend(LT_U_I32);
+                    then(func);
+
+                    Self::gen_record_field_addr(func, value, i);
+                    func.gen_load(MEM_I32_LOAD, 1 + 2 * wasm::BoxType::Record.size());
+                    let field_value = func.gen_local_set(MEM_PTR_TY, None, None);
+                    self.gen_pattern_test(func, &field.pattern, field_value);
+                    then(func);
+                }
+            }
+            ast::Pattern::Variant(variant) => {
+                gen_tag_eq(func, wasm::BoxType::Variant);
+                then(func);
+
+                // Names are interned, so comparing pointers is enough
+                func.gen_local_get(value);
+                func.gen_load(MEM_I32_LOAD, 1);
+                func.body.extend(CONST_I32);
+                func.body
+                    .extend(self.intern_name(&variant.info.name) as i32);
+                func.body.extend(EQ_I32);
+                then(func);
+
+                for (i, field) in variant.fields.iter().enumerate() {
+                    func.gen_local_get(value);
+                    func.gen_load(MEM_I32_LOAD, Self::variant_field_offset(i));
+                    let field_value = func.gen_local_set(MEM_PTR_TY, None, None);
+                    self.gen_pattern_test(func, field, field_value);
+                    then(func);
+                }
+            }
+        }
+
+        func.body.extend([CONST_I32, 1]);
+        for _ in 0..num_checks {
+            func.body.extend([ELSE, CONST_I32, 0, END]);
+        }
+    }
+
+    /// Binds the names in `pattern`, assuming that `value` matches it.
+    fn gen_pattern_bind(
+        &mut self,
+        func: &mut wasm::Func,
+        pattern: &ast::Pattern,
+        value: wasm::LocalIdx,
+    ) {
+        use wasm::binary::{ADD_I32, CONST_I32, MEM_COPY, MEM_I32_LOAD, MUL_I32, SUB_I32};
+
+        match pattern {
+            ast::Pattern::Identifier(ident) => {
+                func.gen_local_get(value);
+                Self::gen_ident_set(func, ident);
+            }
+            ast::Pattern::Wildcard(_) | ast::Pattern::Literal(_) => {}
+            ast::Pattern::List(list) => {
+                for (i, item) in list.items.iter().enumerate() {
+                    func.gen_local_get(value);
+                    func.gen_load(MEM_I32_LOAD, Self::list_item_offset(i));
+                    let item_value = func.gen_local_set(MEM_PTR_TY, None, None);
+                    self.gen_pattern_bind(func, item, item_value);
+                }
+
+                let Some(rest) = list.rest.as_deref() else {
+                    return;
+                };
+                if let ast::Pattern::Wildcard(_) = rest {
+                    return;
+                }
+
+                // Copy the rest of the items into a new list
+                let skipped = list.items.len();
+                func.gen_local_get(value);
+                func.gen_load(MEM_I32_LOAD, 1);
+                func.body.extend(CONST_I32);
+                func.body.extend(skipped as i32);
+                func.body.extend(SUB_I32);
+                let rest_len = func.gen_local_tee(wasm::ValType::I32, None, None);
+                func.body.extend(CONST_I32);
+                func.body.extend(wasm::BoxType::List.size());
+                func.body.extend(MUL_I32);
+                let items_size = func.gen_local_set(wasm::ValType::I32, None, None);
+
+                let rest_value = self.mem_store.gen_alloc_ptr_get(func);
+                func.gen_local_get(items_size);
+                func.body.extend(ADD_I32);
+                func.body.extend(CONST_I32);
+                func.body.extend(Self::list_item_offset(0));
+                func.body.extend(ADD_I32);
+                self.mem_store.gen_alloc_ptr_set(func);
+
+                func.gen_local_get(rest_value);
+                func.body.extend(CONST_I32);
+                func.body.extend(wasm::BoxType::List.tag());
+                func.gen_store(wasm::binary::MEM_I32_STORE_8, 0);
+                func.gen_local_get(rest_value);
+                func.gen_local_get(rest_len);
+                func.gen_store(wasm::binary::MEM_I32_STORE, 1);
+
+                func.gen_local_get(rest_value);
+                func.body.extend(CONST_I32);
+                func.body.extend(Self::list_item_offset(0));
+                func.body.extend(ADD_I32);
+                func.gen_local_get(value);
+                func.body.extend(CONST_I32);
+                func.body.extend(Self::list_item_offset(skipped));
+                func.body.extend(ADD_I32);
+                func.gen_local_get(items_size);
+                func.body.extend(MEM_COPY);
+                func.body.extend([0x00u8, 0x00]); // Both in memory 0
+
+                self.gen_pattern_bind(func, rest, rest_value);
+            }
+            ast::Pattern::Record(record) => {
+                func.gen_local_get(value);
+                func.gen_load(MEM_I32_LOAD, 1);
+                let len = func.gen_local_set(wasm::ValType::I32, None, None);
+                for field in &record.fields {
+                    let name = self.intern_name(&field.name);
+                    let i = Self::gen_record_find(func, value, len, name);
+                    Self::gen_record_field_addr(func, value, i);
+                    func.gen_load(MEM_I32_LOAD, 1 + 2 * wasm::BoxType::Record.size());
+                    let field_value = func.gen_local_set(MEM_PTR_TY, None, None);
+                    self.gen_pattern_bind(func, &field.pattern, field_value);
+                }
+            }
+            ast::Pattern::Variant(variant) => {
+                for (i, field) in variant.fields.iter().enumerate() {
+                    func.gen_local_get(value);
+                    func.gen_load(MEM_I32_LOAD, Self::variant_field_offset(i));
+                    let field_value = func.gen_local_set(MEM_PTR_TY, None, None);
+                    self.gen_pattern_bind(func, field, field_value);
+                }
+            }
+        }
+    }
+
+    /// How far the `i`th field of a variant is from the start of its box,
+    /// after the tag, name and number of fields.
+    fn variant_field_offset(i: usize) -> u32 {
+        1 + wasm::BoxType::Variant.size() * (2 + i as u32)
+    }
+
+    /// How far the `i`th item of a list is from the start of its box, after
+    /// the tag and length.
+    fn list_item_offset(i: usize) -> u32 {
+        1 + wasm::BoxType::List.size() * (1 + i as u32)
+    }
+
+    /// Decodes the (LEB128) length of the boxed string `value`, and returns
+    /// locals with the length and the address of its first byte.
+    fn gen_str_len(
+        func: &mut wasm::Func,
+        value: wasm::LocalIdx,
+    ) -> (wasm::LocalIdx, wasm::LocalIdx) {
+        use wasm::binary::{
+            ADD_I32, AND_I32, BR_IF, CONST_I32, END, LOCAL_SET, LOOP, MEM_I32_LOAD_8U, OR_I32,
+            SHL_I32, TY_NEVER,
+        };
+
+        func.body.extend([CONST_I32, 0]);
+        let len = func.gen_local_set(wasm::ValType::I32, None, None);
+        func.body.extend([CONST_I32, 0]);
+        let shift = func.gen_local_set(wasm::ValType::I32, None, None);
+        func.gen_local_get(value);
+        func.body.extend([CONST_I32, 1, ADD_I32]);
+        let bytes = func.gen_local_set(MEM_PTR_TY, None, None);
+
+        func.body.extend([LOOP, TY_NEVER]);
+        {
+            func.gen_local_get(bytes);
+            func.gen_load(MEM_I32_LOAD_8U, 0);
+            let byte = func.gen_local_tee(wasm::ValType::I32, None, None);
+            // Not `[CONST_I32, 0x7F]`, since 0x7F is -1 in signed LEB128
+            func.body.extend(CONST_I32);
+            func.body.extend(0x7F);
+            func.body.extend(AND_I32);
+            func.gen_local_get(shift);
+            func.body.extend(SHL_I32);
+            func.gen_local_get(len);
+            func.body.extend([OR_I32, LOCAL_SET]);
+            func.body.extend(len);
+
+            func.gen_local_get(shift);
+            func.body.extend([CONST_I32, 7, ADD_I32, LOCAL_SET]);
+            func.body.extend(shift);
+            func.gen_local_get(bytes);
+            func.body.extend([CONST_I32, 1, ADD_I32, LOCAL_SET]);
+            func.body.extend(bytes);
+
+            // Continue while the continuation bit is set
+            func.gen_local_get(byte);
+            func.body.extend(CONST_I32);
+            func.body.extend(0x80);
+            func.body.extend([AND_I32, BR_IF, 0]);
+        }
+        func.body.extend(END);
+
+        (len, bytes)
+    }
+
     fn gen_func_def<I: IntoIterator<Item = ast::Upvalue>>(
         &mut self,
         func: &mut wasm::Func,
@@ -239,13 +787,27 @@ impl WasmGenState {
 
     fn gen_expr(&mut self, func: &mut wasm::Func, expr: ast::Expr) {
         // NOTE: all expressions must return some value, even if it is nil.
+
+        // Numbers and bools are only boxed once they leave the expression
+        // they are computed in
+        if let Some(ty) = unboxed::unboxed_type(&expr) {
+            return self.gen_boxed(func, expr, ty);
+        }
+
         match expr {
             ast::Expr::Block(block) => self.gen_block_expr(block, func),
             ast::Expr::Call(call) => self.gen_call_expr(call, func),
             ast::Expr::If(if_expr) => self.gen_if_expr(*if_expr, func),
-            ast::Expr::Binary(binary_expr) => self.gen_binary_expr(*binary_expr, func),
-            ast::Expr::Unary(unary_expr) => self.gen_unary_expr(*unary_expr, func),
+            ast::Expr::Match(match_expr) => self.gen_match_expr(*match_expr, func),
+            ast::Expr::Binary(_) | ast::Expr::Unary(_) => {
+                unreachable!("operators are generated by gen_boxed")
+            }
             ast::Expr::Literal(literal) => self.gen_literal_expr(func, literal),
+            ast::Expr::Interpolated(interpolated) => self.gen_interpolated_expr(interpolated, func),
+            ast::Expr::List(list) => self.gen_list_expr(list, func),
+            ast::Expr::Index(index) => self.gen_index_expr(*index, func),
+            ast::Expr::Record(record) => self.gen_record_expr(record, func),
+            ast::Expr::Field(field_access) => self.gen_field_expr(*field_access, func),
             ast::Expr::Identifier(identifier) => self.gen_identifier_expr(identifier, func),
         }
     }
@@ -291,8 +853,7 @@ impl WasmGenState {
     }
 
     fn gen_if_expr(&mut self, if_expr: ast::IfExpr, func: &mut wasm::Func) {
-        self.gen_expr(func, if_expr.condition);
-        func.gen_unbox(wasm::BoxType::Bool);
+        self.gen_unboxed(func, if_expr.condition, wasm::BoxType::Bool);
 
         func.body.extend(wasm::binary::IF);
         // Always return a boxed ptr, even if it's nil
@@ -314,89 +875,139 @@ impl WasmGenState {
         func.body.extend(wasm::binary::END);
     }
 
-    fn gen_binary_expr(&mut self, binary_expr: ast::BinaryExpr, func: &mut wasm::Func) {
-        let (op_ty, ret_ty, instrs) = {
-            use wasm::binary::{
-                ADD_F64, AND_I32, DIV_F64, EQ_F64, GE_F64, GT_F64, LE_F64, LT_F64, MUL_F64, NE_F64,
-                OR_I32, SUB_F64,
-            };
-            use wasm::BoxType::{Bool, Num};
-
-            match binary_expr.op {
-                ast::BinaryOp::Or => (Bool, Bool, OR_I32),
-                ast::BinaryOp::And => (Bool, Bool, AND_I32),
-                ast::BinaryOp::NotEq => (Num, Bool, NE_F64),
-                ast::BinaryOp::Eq => (Num, Bool, EQ_F64),
-                ast::BinaryOp::Greater => (Num, Bool, GT_F64),
-                ast::BinaryOp::GreaterEq => (Num, Bool, GE_F64),
-                ast::BinaryOp::Less => (Num, Bool, LT_F64),
-                ast::BinaryOp::LessEq => (Num, Bool, LE_F64),
-                ast::BinaryOp::Subtract => (Num, Num, SUB_F64),
-                ast::BinaryOp::Add => (Num, Num, ADD_F64),
-                ast::BinaryOp::Divide => (Num, Num, DIV_F64),
-                ast::BinaryOp::Multiply => (Num, Num, MUL_F64),
+    /// Each arm is an `if` in the `else` of the one before it, and there is a
+    /// trap at the very end for when no arm matches.
+    fn gen_match_expr(&mut self, match_expr: ast::Match, func: &mut wasm::Func) {
+        use wasm::binary::{CONST_I32, ELSE, END, IF, TRAP, TY_I32};
+
+        self.gen_expr(func, match_expr.value);
+        let value = func.gen_local_set(MEM_PTR_TY, None, None);
+
+        let num_arms = match_expr.arms.len();
+        for arm in match_expr.arms {
+            self.gen_pattern_test(func, &arm.pattern, value);
+            if let Some(guard) = arm.guard {
+                // The guard can use the names bound by the pattern
+                func.body.extend([IF, TY_I32]);
+                self.gen_pattern_bind(func, &arm.pattern, value);
+                self.gen_unboxed(func, guard, wasm::BoxType::Bool);
+                func.body.extend([ELSE, CONST_I32, 0, END]);
+
+                func.body.extend((IF, MEM_PTR_TY));
+            } else {
+                func.body.extend((IF, MEM_PTR_TY));
+                self.gen_pattern_bind(func, &arm.pattern, value);
             }
-        };
-
-        self.gen_expr(func, binary_expr.rhs);
-        let rhs_idx = func.gen_local_set(MEM_PTR_TY, None, None);
-
-        self.gen_expr(func, binary_expr.lhs);
-
-        let rebox_ptr = self.mem_store.alloc(func, ret_ty);
-        func.unwrap_box(op_ty, rebox_ptr, |func| {
-            func.gen_local_get(rhs_idx);
-            func.gen_unbox(op_ty);
+            self.gen_expr(func, arm.body);
+            func.body.extend(ELSE);
+        }
 
-            func.body.extend(instrs)
-        });
+        func.body.extend(TRAP);
+        for _ in 0..num_arms {
+            func.body.extend(END);
+        }
     }
 
-    fn gen_unary_expr(&mut self, unary_expr: ast::UnaryExpr, func: &mut wasm::Func) {
-        self.gen_expr(func, unary_expr.rhs);
-        match unary_expr.op {
-            ast::UnaryOp::Not => {
-                let rebox_ptr = self.mem_store.alloc(func, wasm::BoxType::Bool);
-                func.unwrap_box(wasm::BoxType::Bool, rebox_ptr, |func| {
+    /// Generates a raw `f64` or `i32`, depending on `ty`. Expressions that
+    /// aren't known to be that type are generated boxed, and then unboxed.
+    ///
+    /// `[] -> [ty]`
+    fn gen_unboxed(&mut self, func: &mut wasm::Func, expr: ast::Expr, ty: wasm::BoxType) {
+        if unboxed::unboxed_type(&expr) != Some(ty) {
+            self.gen_expr(func, expr);
+            func.gen_unbox(ty);
+            return;
+        }
+
+        match expr {
+            ast::Expr::Literal(literal) => match literal.value {
+                ast::LiteralValue::Bool(b) => {
+                    func.body.extend(wasm::binary::CONST_I32);
+                    func.body.extend(b);
+                }
+                ast::LiteralValue::Number(n) => {
+                    func.body.extend(wasm::binary::CONST_F64);
+                    func.body.extend(n);
+                }
+                ast::LiteralValue::Str(_) | ast::LiteralValue::Nil => {
+                    unreachable!("strings and nil are never unboxed")
+                }
+            },
+            ast::Expr::Binary(binary_expr) => {
+                let (op_ty, _, instr) = unboxed::binary_op(&binary_expr.op);
+                self.gen_unboxed(func, binary_expr.lhs, op_ty);
+                self.gen_unboxed(func, binary_expr.rhs, op_ty);
+                func.body.extend(instr);
+            }
+            ast::Expr::Unary(unary_expr) => {
+                self.gen_unboxed(func, unary_expr.rhs, ty);
+                match unary_expr.op {
                     // Use XOR 0x1 as NOT
                     // 0x0 xor 0x1 = 0x1
                     // 0x1 xor 0x1 = 0x0
-                    func.body
-                        .extend([wasm::binary::CONST_I32, 0x1, wasm::binary::XOR_I32])
-                })
+                    ast::UnaryOp::Not => {
+                        func.body
+                            .extend([wasm::binary::CONST_I32, 0x1, wasm::binary::XOR_I32])
+                    }
+                    ast::UnaryOp::Negate => func.body.extend(wasm::binary::NEG_F64),
+                }
             }
-            ast::UnaryOp::Negate => {
-                let rebox_ptr = self.mem_store.alloc(func, wasm::BoxType::Num);
-                func.unwrap_box(wasm::BoxType::Num, rebox_ptr, |func| {
-                    func.body.extend(wasm::binary::NEG_F64)
-                })
+            ast::Expr::Block(block) => {
+                for stmt in block.stmts {
+                    self.gen_stmt(func, stmt);
+                }
+                let return_expr = block
+                    .return_expr
+                    .expect("unboxed block should have a return expr");
+                self.gen_unboxed(func, *return_expr, ty);
             }
+            ast::Expr::If(if_expr) => {
+                self.gen_unboxed(func, if_expr.condition, wasm::BoxType::Bool);
+
+                func.body.extend(wasm::binary::IF);
+                func.body.extend(wasm::ValType::from(ty));
+                self.gen_unboxed(func, ast::Expr::Block(if_expr.then_block), ty);
+
+                func.body.extend(wasm::binary::ELSE);
+                match if_expr
+                    .else_block
+                    .expect("unboxed if should have an else block")
+                {
+                    ast::ElseBlock::ElseIf(if_expr) => {
+                        self.gen_unboxed(func, ast::Expr::If(if_expr), ty)
+                    }
+                    ast::ElseBlock::Else(block) => {
+                        self.gen_unboxed(func, ast::Expr::Block(block), ty)
+                    }
+                }
+
+                func.body.extend(wasm::binary::END);
+            }
+            _ => unreachable!("unboxed_type should only know the type of the above"),
         }
     }
 
+    /// Boxes an expression that `unboxed_type` knows the type of. Everything
+    /// inside of it is generated unboxed, so there is only one allocation.
+    fn gen_boxed(&mut self, func: &mut wasm::Func, expr: ast::Expr, ty: wasm::BoxType) {
+        self.gen_unboxed(func, expr, ty);
+        let value_idx = func.gen_local_set(ty.into(), None, None);
+
+        let ptr = self.mem_store.alloc(func, ty);
+        func.gen_box(
+            ptr,
+            [|func: &mut wasm::Func| {
+                func.gen_local_get(value_idx);
+            }],
+        );
+    }
+
     fn gen_literal_expr(&mut self, func: &mut wasm::Func, literal: ast::Literal) {
-        match literal {
-            ast::Literal::Bool(b) => {
-                let ptr = self.mem_store.alloc(func, wasm::BoxType::Bool);
-                func.gen_box(
-                    ptr,
-                    [|func: &mut wasm::Func| {
-                        func.body.extend(wasm::binary::CONST_I32);
-                        func.body.extend(b);
-                    }],
-                )
+        match literal.value {
+            ast::LiteralValue::Bool(_) | ast::LiteralValue::Number(_) => {
+                unreachable!("numbers and bools are generated by gen_boxed")
             }
-            ast::Literal::Number(n) => {
-                let ptr = self.mem_store.alloc(func, wasm::BoxType::Num);
-                func.gen_box(
-                    ptr,
-                    [|func: &mut wasm::Func| {
-                        func.body.extend(wasm::binary::CONST_F64);
-                        func.body.extend(n);
-                    }],
-                )
-            }
-            ast::Literal::Str(s) => {
+            ast::LiteralValue::Str(s) => {
                 // Encode as a WasmVec of UTF-8 chars
                 let mut buf = WasmVec::new();
                 buf.extend(s.into_bytes());
@@ -417,8 +1028,352 @@ impl WasmGenState {
                     }),
                 );
             }
-            ast::Literal::Nil => self.gen_boxed_nil(func),
+            ast::LiteralValue::Nil => self.gen_boxed_nil(func),
+        }
+    }
+
+    fn gen_interpolated_expr(&mut self, interpolated: ast::Interpolated, func: &mut wasm::Func) {
+        use wasm::binary::{ADD_I32, AND_I32, CALL, CONST_I32, OR_I32, SHR_U_I32, SUB_I32};
+
+        // Evaluate all the parts first, because they may allocate, and the
+        // string is built in place at the end of memory.
+        let parts = interpolated
+            .parts
+            .into_iter()
+            .map(|part| {
+                self.gen_expr(func, part);
+                func.gen_local_set(MEM_PTR_TY, None, None)
+            })
+            .collect::<Vec<_>>();
+
+        // The length isn't known until the end, so always use a 5 byte
+        // (padded) LEB128 for it, which fits any u32.
+        const LEN_SIZE: i32 = 5;
+        let start = self.mem_store.gen_alloc_ptr_get(func);
+        func.body.extend([CONST_I32, 1 + LEN_SIZE as u8, ADD_I32]);
+        let cursor = func.gen_local_set(MEM_PTR_TY, None, None);
+
+        // Write each part directly after the previous one
+        for part in parts {
+            func.gen_local_get(cursor);
+
+            func.gen_local_get(part);
+            func.gen_local_get(cursor);
+            func.body.extend(CALL);
+            func.body.extend(self.host_to_str);
+
+            func.body.extend(ADD_I32);
+            func.body.extend(wasm::binary::LOCAL_SET);
+            func.body.extend(cursor);
+        }
+
+        func.gen_local_get(cursor);
+        self.mem_store.gen_alloc_ptr_set(func);
+
+        // Tag byte
+        func.gen_local_get(start);
+        func.body.extend(CONST_I32);
+        func.body.extend(wasm::BoxType::String.tag());
+        func.gen_store(wasm::binary::MEM_I32_STORE_8, 0);
+
+        // Length, as `cursor - start - header`
+        func.gen_local_get(cursor);
+        func.gen_local_get(start);
+        func.body.extend(SUB_I32);
+        func.body.extend([CONST_I32, 1 + LEN_SIZE as u8, SUB_I32]);
+        let len = func.gen_local_set(wasm::ValType::I32, None, None);
+        for i in 0..LEN_SIZE {
+            func.gen_local_get(start);
+            func.gen_local_get(len);
+            func.body.extend(CONST_I32);
+            func.body.extend(7 * i);
+            func.body.extend(SHR_U_I32);
+            // Not `[CONST_I32, 0x7F]`, since 0x7F is -1 in signed LEB128
+            func.body.extend(CONST_I32);
+            func.body.extend(0x7F);
+            func.body.extend(AND_I32);
+            if i != LEN_SIZE - 1 {
+                // Continuation bit
+                func.body.extend(CONST_I32);
+                func.body.extend(0x80);
+                func.body.extend(OR_I32);
+            }
+            func.gen_store(wasm::binary::MEM_I32_STORE_8, 1 + i as u32);
+        }
+
+        func.gen_local_get(start);
+    }
+
+    fn gen_list_expr(&mut self, list: ast::List, func: &mut wasm::Func) {
+        // Evaluate the items first, because they may allocate
+        let items = list
+            .items
+            .into_iter()
+            .map(|item| {
+                self.gen_expr(func, item);
+                func.gen_local_set(MEM_PTR_TY, None, None)
+            })
+            .collect::<Vec<_>>();
+
+        let len = items.len() as u32;
+        let ptr = self.mem_store.alloc_n(func, wasm::BoxType::List, 1 + len);
+        let items = &items;
+        func.gen_box(
+            ptr,
+            (0..=len)
+                .map(|i| {
+                    move |func: &mut wasm::Func| match i {
+                        0 => {
+                            func.body.extend(wasm::binary::CONST_I32);
+                            func.body.extend(len as i32);
+                        }
+                        i => func.gen_local_get(items[i as usize - 1]),
+                    }
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    fn gen_index_expr(&mut self, index: ast::Index, func: &mut wasm::Func) {
+        use wasm::binary::{
+            ADD_I32, CONST_F64, CONST_I32, CONVERT_I32_U_F64, END, FLOOR_F64, GE_F64, IF, LT_F64,
+            MEM_I32_LOAD, MUL_I32, NE_F64, OR_I32, TRAP, TRUNC_F64_U_I32, TY_NEVER,
+        };
+
+        self.gen_expr(func, index.target);
+        let list = func.gen_local_tee(MEM_PTR_TY, None, None);
+        // The length is the first u32
+        func.gen_unbox(wasm::BoxType::List);
+        let len = func.gen_local_set(wasm::ValType::I32, None, None);
+
+        self.gen_unboxed(func, index.index, wasm::BoxType::Num);
+        let index = func.gen_local_set(wasm::ValType::F64, None, None);
+
+        // Bounds check. The index has to be a whole number in `0..len`, like
+        // in the interpreter, so anything else traps before it's truncated.
+        // (`NaN` isn't equal to its own floor, so it traps too.)
+        func.gen_local_get(index);
+        func.body.extend(CONST_F64);
+        func.body.extend(0.0);
+        func.body.extend(LT_F64);
+        func.gen_local_get(index);
+        func.gen_local_get(index);
+        func.body.extend(FLOOR_F64);
+        func.body.extend(NE_F64);
+        func.body.extend(OR_I32);
+        func.gen_local_get(index);
+        func.gen_local_get(len);
+        func.body.extend(CONVERT_I32_U_F64);
+        func.body.extend(GE_F64);
+        func.body.extend(OR_I32);
+        func.body.extend([IF, TY_NEVER, TRAP, END]);
+
+        func.gen_local_get(index);
+        func.body.extend(TRUNC_F64_U_I32);
+        let i = func.gen_local_set(wasm::ValType::I32, None, None);
+
+        // The item is at `list + tag + len + i * size`
+        func.gen_local_get(i);
+        func.body.extend(CONST_I32);
+        func.body.extend(wasm::BoxType::List.size());
+        func.body.extend(MUL_I32);
+        func.gen_local_get(list);
+        func.body.extend(ADD_I32);
+        func.gen_load(MEM_I32_LOAD, 1 + wasm::BoxType::List.size());
+    }
+
+    fn gen_record_expr(&mut self, record: ast::Record, func: &mut wasm::Func) {
+        use wasm::binary::{ADD_I32, CONST_I32, MEM_COPY, MEM_I32_STORE, MUL_I32};
+
+        let base = record.base.map(|base| {
+            self.gen_expr(func, *base);
+            func.gen_local_set(MEM_PTR_TY, None, None)
+        });
+        // Evaluate the values in order first, because they may allocate
+        let mut fields = record
+            .fields
+            .into_iter()
+            .map(|field| {
+                self.gen_expr(func, field.value);
+                let value = func.gen_local_set(MEM_PTR_TY, None, None);
+                (field.name, value)
+            })
+            .collect::<Vec<_>>();
+
+        let Some(base) = base else {
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let fields = fields
+                .into_iter()
+                .map(|(name, value)| (self.intern_name(&name), value))
+                .collect::<Vec<_>>();
+            let fields = &fields;
+
+            let len = fields.len() as u32;
+            let ptr = self
+                .mem_store
+                .alloc_n(func, wasm::BoxType::Record, 1 + 2 * len);
+            func.gen_box(
+                ptr,
+                (0..=2 * len)
+                    .map(|i| {
+                        move |func: &mut wasm::Func| match i {
+                            0 => {
+                                func.body.extend(CONST_I32);
+                                func.body.extend(len as i32);
+                            }
+                            i if i % 2 == 1 => {
+                                func.body.extend(CONST_I32);
+                                func.body.extend(fields[i as usize / 2].0 as i32);
+                            }
+                            i => func.gen_local_get(fields[i as usize / 2 - 1].1),
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            return;
+        };
+
+        // Copy the base record, and then replace the updated fields
+        func.gen_local_get(base);
+        func.gen_unbox(wasm::BoxType::Record);
+        let len = func.gen_local_tee(wasm::ValType::I32, None, None);
+        func.body.extend(CONST_I32);
+        func.body.extend(2 * wasm::BoxType::Record.size());
+        func.body.extend(MUL_I32);
+        func.body.extend(CONST_I32);
+        func.body.extend(1 + wasm::BoxType::Record.size());
+        func.body.extend(ADD_I32);
+        let size = func.gen_local_set(wasm::ValType::I32, None, None);
+
+        let ptr = self.mem_store.gen_alloc_ptr_get(func);
+        func.gen_local_get(size);
+        func.body.extend(ADD_I32);
+        self.mem_store.gen_alloc_ptr_set(func);
+
+        func.gen_local_get(ptr);
+        func.gen_local_get(base);
+        func.gen_local_get(size);
+        func.body.extend(MEM_COPY);
+        func.body.extend([0x00u8, 0x00]); // Both in memory 0
+
+        for (name, value) in fields {
+            let name = self.intern_name(&name);
+            let i = Self::gen_record_find(func, ptr, len, name);
+            Self::gen_record_field_check(func, len, i);
+            Self::gen_record_field_addr(func, ptr, i);
+            func.gen_local_get(value);
+            func.gen_store(MEM_I32_STORE, 1 + 2 * wasm::BoxType::Record.size());
+        }
+
+        func.gen_local_get(ptr);
+    }
+
+    fn gen_field_expr(&mut self, field_access: ast::FieldAccess, func: &mut wasm::Func) {
+        self.gen_expr(func, field_access.target);
+        let record = func.gen_local_tee(MEM_PTR_TY, None, None);
+        // The number of fields is the first u32
+        func.gen_unbox(wasm::BoxType::Record);
+        let len = func.gen_local_set(wasm::ValType::I32, None, None);
+
+        let name = self.intern_name(&field_access.field);
+        let i = Self::gen_record_find(func, record, len, name);
+        Self::gen_record_field_check(func, len, i);
+        Self::gen_record_field_addr(func, record, i);
+        func.gen_load(
+            wasm::binary::MEM_I32_LOAD,
+            1 + 2 * wasm::BoxType::Record.size(),
+        );
+    }
+
+    /// Finds which field of `record` is named `name`, and returns a local
+    /// with its index. The index is `len` if there is no such field.
+    ///
+    /// `[] -> []`
+    fn gen_record_find(
+        func: &mut wasm::Func,
+        record: wasm::LocalIdx,
+        len: wasm::LocalIdx,
+        name: u32,
+    ) -> wasm::LocalIdx {
+        use wasm::binary::{
+            ADD_I32, BLOCK, BR, BR_IF, CONST_I32, END, GE_U_I32, IF, LOCAL_SET, LOOP, MEM_I32_LOAD,
+            NE_I32, TY_NEVER,
+        };
+
+        func.body.extend([CONST_I32, 0]);
+        let i = func.gen_local_set(wasm::ValType::I32, None, None);
+
+        func.body.extend([BLOCK, TY_NEVER, LOOP, TY_NEVER]);
+        {
+            // Ran out of fields (break out of the block)
+            func.gen_local_get(i);
+            func.gen_local_get(len);
+            func.body.extend(GE_U_I32);
+            func.body.extend([BR_IF, 1]);
+
+            // Names are interned, so comparing pointers is enough
+            Self::gen_record_field_addr(func, record, i);
+            func.gen_load(MEM_I32_LOAD, 1 + wasm::BoxType::Record.size());
+            func.body.extend(CONST_I32);
+            func.body.extend(name as i32);
+            func.body.extend(NE_I32);
+            func.body.extend([IF, TY_NEVER]);
+            {
+                func.gen_local_get(i);
+                func.body.extend([CONST_I32, 1, ADD_I32, LOCAL_SET]);
+                func.body.extend(i);
+                // Continue the loop (the `if` is depth 0)
+                func.body.extend([BR, 1]);
+            }
+            func.body.extend(END);
         }
+        func.body.extend([END, END]);
+
+        i
+    }
+
+    /// Traps if the field found by [`Self::gen_record_find`] doesn't exist.
+    ///
+    /// `[] -> []`
+    fn gen_record_field_check(func: &mut wasm::Func, len: wasm::LocalIdx, i: wasm::LocalIdx) {
+        use wasm::binary::{END, GE_U_I32, IF, TRAP, TY_NEVER};
+
+        func.gen_local_get(i);
+        func.gen_local_get(len);
+        func.body.extend([GE_U_I32, IF, TY_NEVER, TRAP, END]);
+    }
+
+    /// Puts the address of the `i`th field of `record` on the stack, offset
+    /// so that the field's name is 5 bytes after it and its value 9 bytes
+    /// after it (to skip the tag and length).
+    ///
+    /// `[] -> [MEM_PTR_TY]`
+    fn gen_record_field_addr(func: &mut wasm::Func, record: wasm::LocalIdx, i: wasm::LocalIdx) {
+        use wasm::binary::{ADD_I32, CONST_I32, MUL_I32};
+
+        func.gen_local_get(i);
+        func.body.extend(CONST_I32);
+        func.body.extend(2 * wasm::BoxType::Record.size());
+        func.body.extend(MUL_I32);
+        func.gen_local_get(record);
+        func.body.extend(ADD_I32);
+    }
+
+    /// Returns the address of a boxed string of `name`, which is the same for
+    /// every use of the name.
+    fn intern_name(&mut self, name: &str) -> u32 {
+        if let Some(addr) = self.interned_names.get(name) {
+            return *addr;
+        }
+
+        let addr = self.static_data.len() as u32;
+        self.static_data.push(wasm::BoxType::String.tag());
+        let mut bytes = WasmVec::new();
+        bytes.extend(name.bytes());
+        self.static_data.extend(bytes.into_bytes());
+
+        self.interned_names.insert(name.to_string(), addr);
+        addr
     }
 
     fn gen_identifier_expr(&mut self, identifier: ast::Identifier, func: &mut wasm::Func) {
@@ -464,6 +1419,25 @@ impl MemStore {
         self.alloc_raw(func, box_ty, n, true)
     }
 
+    /// Puts the address of the next free memory on the stack, and returns a
+    /// local with it as well.
+    ///
+    /// `[] -> [MEM_PTR_TY]`
+    pub fn gen_alloc_ptr_get(&self, func: &mut wasm::Func) -> wasm::LocalIdx {
+        func.body.extend(wasm::binary::GLOBAL_GET);
+        func.body.extend(self.global_mem_alloc_ptr);
+        func.gen_local_tee(MEM_PTR_TY, None, None)
+    }
+
+    /// Sets the address of the next free memory, for when the size of an
+    /// allocation is only known at runtime.
+    ///
+    /// `[MEM_PTR_TY] -> []`
+    pub fn gen_alloc_ptr_set(&self, func: &mut wasm::Func) {
+        func.body.extend(wasm::binary::GLOBAL_SET);
+        func.body.extend(self.global_mem_alloc_ptr);
+    }
+
     pub fn alloc_raw(
         &mut self,
         func: &mut wasm::Func,
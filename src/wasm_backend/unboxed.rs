@@ -0,0 +1,80 @@
+//! Which expressions can be generated as raw `f64`s and `i32`s instead of
+//! boxes.
+//!
+//! Every arithmetic operator traps unless it gets numbers, and every
+//! comparison and logical operator returns a bool, so their results are known
+//! without running anything. Those values only need to be boxed when they
+//! leave the expression, e.g. when they are bound or passed to a function.
+
+use crate::ast;
+
+use super::wasm::BoxType;
+
+/// The type `expr` is known to evaluate to, if it is a `Num` or a `Bool`.
+pub fn unboxed_type(expr: &ast::Expr) -> Option<BoxType> {
+    match expr {
+        ast::Expr::Literal(literal) => match literal.value {
+            ast::LiteralValue::Number(_) => Some(BoxType::Num),
+            ast::LiteralValue::Bool(_) => Some(BoxType::Bool),
+            ast::LiteralValue::Str(_) | ast::LiteralValue::Nil => None,
+        },
+        ast::Expr::Binary(binary_expr) => Some(binary_op(&binary_expr.op).1),
+        ast::Expr::Unary(unary_expr) => Some(unary_op(&unary_expr.op)),
+        ast::Expr::Block(block) => block_type(block),
+        ast::Expr::If(if_expr) => if_type(if_expr),
+        ast::Expr::Call(_)
+        | ast::Expr::Match(_)
+        | ast::Expr::Interpolated(_)
+        | ast::Expr::List(_)
+        | ast::Expr::Index(_)
+        | ast::Expr::Record(_)
+        | ast::Expr::Field(_)
+        | ast::Expr::Identifier(_) => None,
+    }
+}
+
+fn if_type(if_expr: &ast::IfExpr) -> Option<BoxType> {
+    let then_ty = block_type(&if_expr.then_block)?;
+    // Without an `else`, an `if` can be `nil`
+    let else_ty = match if_expr.else_block.as_ref()? {
+        ast::ElseBlock::ElseIf(if_expr) => if_type(if_expr)?,
+        ast::ElseBlock::Else(block) => block_type(block)?,
+    };
+    (then_ty == else_ty).then_some(then_ty)
+}
+
+fn block_type(block: &ast::Block) -> Option<BoxType> {
+    unboxed_type(block.return_expr.as_ref()?)
+}
+
+/// The type of the operands, the type of the result, and the instruction.
+pub fn binary_op(op: &ast::BinaryOp) -> (BoxType, BoxType, u8) {
+    use super::wasm::binary::{
+        ADD_F64, AND_I32, DIV_F64, EQ_F64, GE_F64, GT_F64, LE_F64, LT_F64, MUL_F64, NE_F64, OR_I32,
+        SUB_F64,
+    };
+    use BoxType::{Bool, Num};
+
+    match op {
+        ast::BinaryOp::Or => (Bool, Bool, OR_I32),
+        ast::BinaryOp::And => (Bool, Bool, AND_I32),
+        ast::BinaryOp::NotEq => (Num, Bool, NE_F64),
+        ast::BinaryOp::Eq => (Num, Bool, EQ_F64),
+        ast::BinaryOp::Greater => (Num, Bool, GT_F64),
+        ast::BinaryOp::GreaterEq => (Num, Bool, GE_F64),
+        ast::BinaryOp::Less => (Num, Bool, LT_F64),
+        ast::BinaryOp::LessEq => (Num, Bool, LE_F64),
+        ast::BinaryOp::Subtract => (Num, Num, SUB_F64),
+        ast::BinaryOp::Add => (Num, Num, ADD_F64),
+        ast::BinaryOp::Divide => (Num, Num, DIV_F64),
+        ast::BinaryOp::Multiply => (Num, Num, MUL_F64),
+    }
+}
+
+/// The type of both the operand and the result.
+pub fn unary_op(op: &ast::UnaryOp) -> BoxType {
+    match op {
+        ast::UnaryOp::Not => BoxType::Bool,
+        ast::UnaryOp::Negate => BoxType::Num,
+    }
+}
@@ -23,6 +23,7 @@ pub struct Module {
     pub export_sec: Option<ExportSection>,
     pub start_sec: Option<StartSection>,
     pub elem_sec: Option<ElemSection>,
+    pub data_sec: Option<DataSection>,
     pub name_sec: NameSection,
 }
 
@@ -62,6 +63,7 @@ impl IntoBytes for Module {
         buf.extend(self.start_sec.into_bytes());
         buf.extend(self.elem_sec.into_bytes());
         buf.extend(code_sec.into_bytes());
+        buf.extend(self.data_sec.into_bytes());
         buf.extend(self.name_sec.into_bytes());
         buf
     }
@@ -157,9 +159,17 @@ impl IntoBytes for ValType {
 /// The type of value to store in memory.
 ///
 /// # Binary Format
-/// - The 3 least significant bits of the first byte (the one that is pointed
+/// - The 4 least significant bits of the first byte (the one that is pointed
 ///   to) contains a tag indicating the type of the data.
-#[derive(Clone, Copy, Debug)]
+/// - A list is its length (u32), followed by that many pointers to its
+///   (boxed) items.
+/// - A record is its number of fields (u32), followed by a pair of pointers
+///   for each field: its name (a boxed string) and its (boxed) value. Fields
+///   are sorted by name, and names are interned, so they can be compared by
+///   pointer.
+/// - A variant is a pointer to its (interned) name, the number of fields
+///   (u32), and then a pointer to each field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BoxType {
     Ptr,
 
@@ -168,6 +178,9 @@ pub enum BoxType {
     Bool,
     String,
     Func,
+    List,
+    Record,
+    Variant,
 }
 
 impl BoxType {
@@ -182,6 +195,12 @@ impl BoxType {
             BoxType::Func => 32 / 8,
             // A MemIdx (u32)
             BoxType::Ptr => 32 / 8,
+            // The length, and then each item, are all u32s
+            BoxType::List => 32 / 8,
+            // The number of fields, and then each name and value, are all u32s
+            BoxType::Record => 32 / 8,
+            // The name, number of fields, and then each field, are all u32s
+            BoxType::Variant => 32 / 8,
         }
     }
 
@@ -193,6 +212,9 @@ impl BoxType {
             BoxType::String => binary::MEM_I32_STORE_8,
             BoxType::Func => binary::MEM_I32_STORE,
             BoxType::Ptr => binary::MEM_I32_STORE,
+            BoxType::List => binary::MEM_I32_STORE,
+            BoxType::Record => binary::MEM_I32_STORE,
+            BoxType::Variant => binary::MEM_I32_STORE,
         }
     }
 
@@ -204,17 +226,23 @@ impl BoxType {
             BoxType::String => binary::MEM_I32_LOAD_8U,
             BoxType::Func => binary::MEM_I32_LOAD,
             BoxType::Ptr => binary::MEM_I32_LOAD,
+            BoxType::List => binary::MEM_I32_LOAD,
+            BoxType::Record => binary::MEM_I32_LOAD,
+            BoxType::Variant => binary::MEM_I32_LOAD,
         }
     }
 
-    fn tag(&self) -> u8 {
+    pub fn tag(&self) -> u8 {
         match self {
-            BoxType::Nil => 0b000,
-            BoxType::Num => 0b001,
-            BoxType::Bool => 0b010,
-            BoxType::String => 0b011,
-            BoxType::Func => 0b100,
-            BoxType::Ptr => 0b101,
+            BoxType::Nil => 0b0000,
+            BoxType::Num => 0b0001,
+            BoxType::Bool => 0b0010,
+            BoxType::String => 0b0011,
+            BoxType::Func => 0b0100,
+            BoxType::Ptr => 0b0101,
+            BoxType::List => 0b0110,
+            BoxType::Record => 0b0111,
+            BoxType::Variant => 0b1000,
         }
     }
 }
@@ -228,6 +256,9 @@ impl From<BoxType> for ValType {
             BoxType::String => ValType::I32,
             BoxType::Func => ValType::I32,
             BoxType::Ptr => MEM_PTR_TY,
+            BoxType::List => ValType::I32,
+            BoxType::Record => ValType::I32,
+            BoxType::Variant => ValType::I32,
         }
     }
 }
@@ -560,6 +591,24 @@ impl Func {
         self.gen_local_get(idx);
     }
 
+    /// Stores a value to memory, at `offset` bytes past the address.
+    ///
+    /// `[I32, T] -> []`
+    pub fn gen_store(&mut self, instr_store: u8, offset: u32) {
+        self.body.extend(instr_store);
+        self.body.extend(0x00u8); // Align 2^0=1
+        self.body.extend(offset);
+    }
+
+    /// Loads a value from memory, at `offset` bytes past the address.
+    ///
+    /// `[I32] -> [T]`
+    pub fn gen_load(&mut self, instr_load: u8, offset: u32) {
+        self.body.extend(instr_load);
+        self.body.extend(0x00u8); // Align 2^0=1
+        self.body.extend(offset);
+    }
+
     /// Boxes the top item on the stack and returns a pointer to it.
     ///
     /// `[T] -> [I32]`
@@ -646,34 +695,6 @@ impl Func {
             0x00,   // Offset 0
         ]);
     }
-
-    /// Unboxes the pointer on top of the stack, runs `func()` to work with it,
-    /// and then reboxes the top of the stack.
-    ///
-    /// `[I32] -> [I32]`
-    ///
-    /// # Parameters
-    /// - `mem_unbox`: What to unbox into before `func()` is run.
-    /// - `mem_rebox`: What to rebox into after `func()` is run.
-    /// - `func`: The function to run w/ the unboxed value. Must be `[Unbox] -> [Rebox]`.
-    pub fn unwrap_box(
-        &mut self,
-        unbox_ty: BoxType,
-        rebox_ptr: MemPtr,
-        func: impl FnOnce(&mut Self),
-    ) {
-        self.gen_unbox(unbox_ty);
-
-        func(self);
-        let res_idx = self.gen_local_set(rebox_ptr.box_ty.into(), None, None);
-
-        self.gen_box(
-            rebox_ptr,
-            [|func: &mut Self| {
-                func.gen_local_get(res_idx);
-            }],
-        );
-    }
 }
 
 #[derive(Debug)]
@@ -837,6 +858,51 @@ impl IntoBytes for Elem {
     }
 }
 
+#[derive(Debug)]
+pub struct DataSection {
+    segments: WasmVec<Data>,
+}
+
+impl DataSection {
+    pub fn new() -> Self {
+        DataSection {
+            segments: WasmVec::new(),
+        }
+    }
+    pub fn insert(&mut self, segment: Data) {
+        self.segments.extend([segment]);
+    }
+}
+
+impl IntoBytes for DataSection {
+    fn into_bytes(self) -> Vec<u8> {
+        binary::sec_bytes(binary::SEC_DATA, self.segments)
+    }
+}
+
+/// Bytes to copy into memory when the module is instantiated.
+#[derive(Debug)]
+pub struct Data {
+    /// Where in memory the bytes go.
+    pub offset: u32,
+    pub bytes: WasmVec<u8>,
+}
+
+impl IntoBytes for Data {
+    /// Described in <https://webassembly.github.io/spec/core/binary/modules.html#data-section>.
+    /// Only active segments for memory 0 are implemented.
+    fn into_bytes(self) -> Vec<u8> {
+        let mut offset = Expr::new();
+        offset.extend(binary::CONST_I32);
+        offset.extend(self.offset as i32);
+
+        let mut buf = vec![0x00];
+        buf.extend(offset.into_bytes());
+        buf.extend(self.bytes.into_bytes());
+        buf
+    }
+}
+
 #[derive(Debug)]
 pub enum ElemMode {
     // Not all the possibilities, but the other ones start to get more complex
@@ -920,7 +986,7 @@ impl IntoBytes for Limits {
 
 #[derive(Debug, Default)]
 pub struct GlobalSection {
-    pub globals: WasmVec<Global>,
+    globals: Vec<Global>,
 }
 
 impl GlobalSection {
@@ -929,9 +995,9 @@ impl GlobalSection {
         global: Global,
         dbg_info: Option<(&mut NameSection, Name)>,
     ) -> GlobalIdx {
-        let idx = GlobalIdx(self.globals.size());
+        let idx = GlobalIdx(self.globals.len() as u32);
 
-        self.globals.extend([global]);
+        self.globals.push(global);
 
         if let Some((name_sec, name)) = dbg_info {
             name_sec.global(idx, name);
@@ -939,6 +1005,11 @@ impl GlobalSection {
 
         idx
     }
+
+    /// For changing a global's initial value after it was inserted.
+    pub fn get_mut(&mut self, idx: GlobalIdx) -> &mut Global {
+        &mut self.globals[idx.0 as usize]
+    }
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -952,7 +1023,8 @@ impl IntoBytes for GlobalIdx {
 
 impl IntoBytes for GlobalSection {
     fn into_bytes(self) -> Vec<u8> {
-        binary::sec_bytes(binary::SEC_GLOBAL, self.globals)
+        let globals: WasmVec<Global> = self.globals.into_iter().collect();
+        binary::sec_bytes(binary::SEC_GLOBAL, globals)
     }
 }
 
@@ -44,9 +44,13 @@ pub const TY_FUNC: u8 = 0x60;
 
 // Control instructions
 pub const TRAP: u8 = 0x00;
+pub const BLOCK: u8 = 0x02;
+pub const LOOP: u8 = 0x03;
 pub const IF: u8 = 0x04;
 pub const ELSE: u8 = 0x05;
 pub const END: u8 = 0x0B;
+pub const BR: u8 = 0x0C;
+pub const BR_IF: u8 = 0x0D;
 pub const CALL: u8 = 0x10;
 pub const CALL_INDIRECT: u8 = 0x11;
 pub const RETURN_CALL: u8 = 0x12;
@@ -72,6 +76,9 @@ pub const MEM_I32_LOAD_8U: u8 = 0x2D;
 pub const MEM_I32_STORE_8: u8 = 0x3A;
 pub const MEM_I32_LOAD_16U: u8 = 0x2F;
 pub const MEM_I32_STORE_16: u8 = 0x3B;
+/// `memory.copy` (from the bulk memory proposal), followed by the
+/// destination and source memory indexes.
+pub const MEM_COPY: [u8; 2] = [0xFC, 0x0A];
 
 // Numeric
 pub const CONST_I32: u8 = 0x41;
@@ -84,6 +91,8 @@ pub const SUB_I32: u8 = 0x6B;
 pub const MUL_I32: u8 = 0x6C;
 pub const DIV_I32: u8 = 0x6D;
 pub const XOR_I32: u8 = 0x73;
+pub const SHL_I32: u8 = 0x74;
+pub const SHR_U_I32: u8 = 0x76;
 
 pub const NEG_F64: u8 = 0x9A;
 pub const ADD_F64: u8 = 0xA0;
@@ -91,8 +100,11 @@ pub const SUB_F64: u8 = 0xA1;
 pub const MUL_F64: u8 = 0xA2;
 pub const DIV_F64: u8 = 0xA3;
 
+pub const EQZ_I32: u8 = 0x45;
 pub const EQ_I32: u8 = 0x46;
 pub const NE_I32: u8 = 0x47;
+pub const LT_U_I32: u8 = 0x49;
+pub const GE_U_I32: u8 = 0x4F;
 
 pub const EQ_F64: u8 = 0x61;
 pub const NE_F64: u8 = 0x62;
@@ -100,6 +112,10 @@ pub const LT_F64: u8 = 0x63;
 pub const GT_F64: u8 = 0x64;
 pub const LE_F64: u8 = 0x65;
 pub const GE_F64: u8 = 0x66;
+pub const FLOOR_F64: u8 = 0x9C;
+
+pub const TRUNC_F64_U_I32: u8 = 0xAB;
+pub const CONVERT_I32_U_F64: u8 = 0xB8;
 
 // Boolean
 pub const AND_I32: u8 = 0x71;